@@ -4,12 +4,18 @@ use crate::templates::*;
 
 use super::AttentionConfig;
 
+#[derive(Debug, Clone, Copy)]
 pub struct AgnosticConfig {
     pub chan_depth: usize,
     pub max_config: ScanTimings,
     pub residual_config: ReduceTimings,
     pub prod_config: ReduceTimings,
     pub scale_config: FlatmapTimings,
+    /// Latency of the running max and sum updates, each of which depends on the previous one
+    pub recurrence_latency: u64,
+    /// Query rows (or heads) whose scores share the Scan and Reduce stages, see
+    /// [agnostic_attention]. 1 for plain row-major streams.
+    pub interleaved_rows: usize,
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -36,17 +42,9 @@ impl<T: DAMType> DAMType for RunningResult<T> {
     }
 }
 
-#[derive(Clone, Debug, Default)]
-struct Vector<T> {
-    pub value: Vec<T>,
-}
-
-impl<T: DAMType> DAMType for Vector<T> {
-    fn dam_size(&self) -> usize {
-        self.value.iter().map(|x| x.dam_size()).sum()
-    }
-}
-
+/// With `interleaved_rows` R above 1, scores of R independent rows arrive round-robin, with
+/// element j of row r at position j * R + r of each group of R rows, and `v_receiver` has to
+/// carry the V row matching every score in the same order. The output stays row-major.
 pub fn agnostic_attention<'a, T: DAMType + num::Float>(
     builder: &mut ProgramBuilder<'a>,
     qkt_receiver: Receiver<T>,
@@ -57,89 +55,95 @@ pub fn agnostic_attention<'a, T: DAMType + num::Float>(
 where
     T: 'a,
 {
+    assert!(
+        config
+            .query_rows
+            .is_multiple_of(agnostic_config.interleaved_rows),
+        "{} query rows can't be interleaved in groups of {}",
+        config.query_rows,
+        agnostic_config.interleaved_rows
+    );
     let (scan_to_residual_snd, scan_to_residual_rcv) = builder.bounded(agnostic_config.chan_depth);
     let (scan_to_mul_snd, scan_to_mul_rcv) = builder.bounded(agnostic_config.chan_depth);
 
-    builder.add_child(Scan::new(
-        config.seq_len,
-        qkt_receiver,
-        BroadcastSender {
-            targets: vec![scan_to_residual_snd, scan_to_mul_snd],
-        },
-        |new, old| match old {
-            Some(RunningResult {
-                cur_max: old_max,
-                delta_max: _,
-                exp: _,
-                delta_elem: _,
-            }) => {
-                let new_max = new.max(*old_max);
-                let delta_max = *old_max - new_max;
+    builder.add_child(
+        Scan::new(
+            config.seq_len,
+            qkt_receiver,
+            BroadcastSender {
+                targets: vec![scan_to_residual_snd, scan_to_mul_snd],
+            },
+            |new, old| match old {
+                Some(RunningResult {
+                    cur_max: old_max,
+                    delta_max: _,
+                    exp: _,
+                    delta_elem: _,
+                }) => {
+                    let new_max = new.max(*old_max);
+                    let delta_max = *old_max - new_max;
 
-                RunningResult {
-                    cur_max: new_max,
-                    delta_max,
-                    exp: (new - new_max).exp(),
-                    delta_elem: delta_max.exp(),
+                    RunningResult {
+                        cur_max: new_max,
+                        delta_max,
+                        exp: (new - new_max).exp(),
+                        delta_elem: delta_max.exp(),
+                    }
                 }
-            }
-            None => RunningResult {
-                cur_max: new,
-                delta_max: new,
-                exp: T::one(),
-                delta_elem: new.exp(),
+                None => RunningResult {
+                    cur_max: new,
+                    delta_max: new,
+                    exp: T::one(),
+                    delta_elem: new.exp(),
+                },
             },
-        },
-        agnostic_config.max_config,
-    ));
+            agnostic_config.max_config,
+        )
+        .with_recurrence(agnostic_config.recurrence_latency)
+        .with_interleaved_rows(agnostic_config.interleaved_rows),
+    );
 
     let (r_to_div_rep_snd, r_to_div_rep_rcv) = builder.bounded(agnostic_config.chan_depth);
 
-    builder.add_child(Reduce::new(
-        config.seq_len,
-        scan_to_residual_rcv,
-        r_to_div_rep_snd,
-        |RunningResult {
-             cur_max: _,
-             delta_max: _,
-             exp,
-             delta_elem,
-         },
-         old: Option<T>| match old {
-            // On future iterations, r_i^(j) = r_i^(j-1) * delta_ij + e_ij
-            Some(old_val) => old_val * delta_elem + exp,
-            // On the first iteration, r_i^(j) is zero
-            None => exp,
-        },
-        agnostic_config.residual_config,
-    ));
+    builder.add_child(
+        Reduce::new(
+            config.seq_len,
+            scan_to_residual_rcv,
+            r_to_div_rep_snd,
+            |RunningResult {
+                 cur_max: _,
+                 delta_max: _,
+                 exp,
+                 delta_elem,
+             },
+             old: Option<T>| match old {
+                // On future iterations, r_i^(j) = r_i^(j-1) * delta_ij + e_ij
+                Some(old_val) => old_val * delta_elem + exp,
+                // On the first iteration, r_i^(j) is zero
+                None => exp,
+            },
+            agnostic_config.residual_config,
+        )
+        .with_recurrence(Recurrence::Serial {
+            latency: agnostic_config.recurrence_latency,
+        })
+        .with_interleaved_rows(agnostic_config.interleaved_rows),
+    );
 
     let (reduce_to_div_snd, reduce_to_div_rcv) = builder.bounded(agnostic_config.chan_depth);
 
     let (v_vec_snd, v_vec_rcv) = builder.bounded(agnostic_config.chan_depth);
 
     // Read rows of the V matrix as vectors.
-    builder.add_child(Reduce::new(
-        config.vocab_dim,
+    builder.add_child(Pack::new(
         v_receiver,
-        v_vec_snd,
-        move |new, old: Option<Vector<T>>| match old {
-            Some(mut v) => {
-                v.value.push(new);
-                v
-            }
-            None => {
-                let mut v = Vector {
-                    value: Vec::with_capacity(config.vocab_dim),
-                };
-                v.value.push(new);
-                v
-            }
+        BroadcastSender {
+            targets: vec![v_vec_snd],
         },
-        ReduceTimings {
+        config.vocab_dim,
+        PackTimings {
             initiation_interval: 1,
             latency: 1,
-            reset_time: 0,
         },
     ));
 
@@ -153,37 +157,43 @@ where
     ));
 
     // Scale each vector by a compensating factor
-    builder.add_child(Reduce::new(
-        config.seq_len,
-        mul_in_rcv,
-        reduce_to_div_snd,
-        move |Pair(
-            RunningResult {
-                cur_max: _,
-                delta_max: _,
-                exp,
-                delta_elem,
-            },
-            mut v_vector,
-        ),
-              old: Option<Vector<T>>| match old {
-            Some(mut old_val) => {
-                for i in 0..old_val.value.len() {
-                    old_val.value[i] = old_val.value[i] * delta_elem + exp * v_vector.value[i]
-                }
+    builder.add_child(
+        Reduce::new(
+            config.seq_len,
+            mul_in_rcv,
+            reduce_to_div_snd,
+            move |Pair(
+                RunningResult {
+                    cur_max: _,
+                    delta_max: _,
+                    exp,
+                    delta_elem,
+                },
+                mut v_vector,
+            ),
+                  old: Option<Vector<T>>| match old {
+                Some(mut old_val) => {
+                    for i in 0..old_val.value.len() {
+                        old_val.value[i] = old_val.value[i] * delta_elem + exp * v_vector.value[i]
+                    }
 
-                old_val
-            }
-            None => {
-                v_vector.value.iter_mut().for_each(|vec_val| {
-                    *vec_val = *vec_val * exp;
-                });
+                    old_val
+                }
+                None => {
+                    v_vector.value.iter_mut().for_each(|vec_val| {
+                        *vec_val = *vec_val * exp;
+                    });
 
-                v_vector
-            }
-        },
-        agnostic_config.prod_config,
-    ));
+                    v_vector
+                }
+            },
+            agnostic_config.prod_config,
+        )
+        .with_recurrence(Recurrence::Serial {
+            latency: agnostic_config.recurrence_latency,
+        })
+        .with_interleaved_rows(agnostic_config.interleaved_rows),
+    );
 
     let (output_snd, output_rcv) = builder.bounded(agnostic_config.chan_depth);
 
@@ -210,3 +220,40 @@ where
 
     output_rcv
 }
+
+/// Turns a row-major stream into the interleaved order that [agnostic_attention] takes, by
+/// buffering `rows` rows of `row_len` elements and reading them out round-robin.
+/// Every row has room for two, so the next group fills in while this one is read out.
+pub fn interleave_rows<'a, T>(
+    builder: &mut ProgramBuilder<'a>,
+    input: Receiver<T>,
+    row_len: usize,
+    rows: usize,
+) -> Receiver<T>
+where
+    T: DAMType + 'a,
+{
+    let (row_senders, row_receivers): (Vec<_>, Vec<_>) =
+        (0..rows).map(|_| builder.bounded(2 * row_len)).unzip();
+    builder.add_child(Distributor::new(
+        input,
+        row_senders,
+        DistributePolicy::Blocks(row_len),
+        DistributeTimings {
+            initiation_interval: 1,
+            latency: 1,
+        },
+    ));
+    let (output_snd, output_rcv) = builder.bounded(rows);
+    builder.add_child(ReorderBuffer::new(
+        row_receivers,
+        output_snd,
+        1,
+        0,
+        ReorderTimings {
+            initiation_interval: 1,
+            latency: 1,
+        },
+    ));
+    output_rcv
+}
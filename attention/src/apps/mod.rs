@@ -2,11 +2,16 @@ use ndarray::{Array2, ArrayView2, Axis};
 
 pub mod agnostic;
 pub mod naive;
+pub mod parallel;
+pub mod topk;
 
 #[derive(Clone, Copy, Debug)]
 pub struct AttentionConfig {
     pub vocab_dim: usize,
     pub seq_len: usize,
+    /// Query rows per attention matrix that go through this pipeline, N unless they are split
+    /// across lanes
+    pub query_rows: usize,
 }
 
 pub fn compute_attention<T: num::Float + std::fmt::Debug + 'static>(
@@ -14,18 +19,52 @@ pub fn compute_attention<T: num::Float + std::fmt::Debug + 'static>(
     k: ArrayView2<T>,
     v: ArrayView2<T>,
 ) -> Array2<T> {
-    let qk_transpose = q.dot(&k.t());
+    softmax_matmul(q.dot(&k.t()), v)
+}
+
+/// Same as [compute_attention], but scores where `mask` is false are excluded from the softmax.
+pub fn compute_masked_attention<T: num::Float + std::fmt::Debug + 'static>(
+    q: ArrayView2<T>,
+    k: ArrayView2<T>,
+    v: ArrayView2<T>,
+    mask: ArrayView2<bool>,
+) -> Array2<T> {
+    let mut qk_transpose = q.dot(&k.t());
+    qk_transpose.zip_mut_with(&mask, |score, &keep| {
+        if !keep {
+            *score = T::neg_infinity();
+        }
+    });
+    softmax_matmul(qk_transpose, v)
+}
+
+fn softmax_matmul<T: num::Float + std::fmt::Debug + 'static>(
+    qk_transpose: Array2<T>,
+    v: ArrayView2<T>,
+) -> Array2<T> {
+    let rows = qk_transpose.nrows();
     let row_max = qk_transpose.fold_axis(Axis(1), T::min_value(), |x, y| x.max(*y));
-    let normalized = qk_transpose - row_max.into_shape((q.nrows(), 1usize)).unwrap();
+    let normalized = qk_transpose - row_max.into_shape((rows, 1usize)).unwrap();
     let exponentiated = normalized.map(|x| x.exp());
     let row_sum = exponentiated
         .sum_axis(Axis(1))
-        .into_shape((q.nrows(), 1usize))
+        .into_shape((rows, 1usize))
         .unwrap();
     let divided = exponentiated / row_sum;
     divided.dot(&v)
 }
 
+/// Marks the k largest scores of each row.
+pub fn topk_mask<T: num::Float>(scores: ArrayView2<T>, k: usize) -> Array2<bool> {
+    let mut mask = Array2::from_elem(scores.raw_dim(), false);
+    for (scores_row, mut mask_row) in scores.rows().into_iter().zip(mask.rows_mut()) {
+        let mut order: Vec<usize> = (0..scores_row.len()).collect();
+        order.sort_by(|&a, &b| scores_row[b].partial_cmp(&scores_row[a]).unwrap());
+        order.into_iter().take(k).for_each(|i| mask_row[i] = true);
+    }
+    mask
+}
+
 #[cfg(test)]
 mod tests {
     use dam::{
@@ -36,14 +75,18 @@ mod tests {
 
     use crate::{
         apps::{
-            agnostic::{agnostic_attention, AgnosticConfig},
-            compute_attention, AttentionConfig,
+            agnostic::{agnostic_attention, interleave_rows, AgnosticConfig},
+            compute_attention, compute_masked_attention, topk_mask, AttentionConfig,
+        },
+        templates::{
+            BatchBroadcast, DistributeTimings, MapTimings, Matmul, MatmulBehavior, MatmulTiming,
+            PackTimings, ReduceTimings, ReorderTimings, RepeatTimings, ScanTimings, ShapeInfo,
+            SystolicDataflow, SystolicGrid, SystolicMatmul, TopKTimings, VectorTimings,
         },
-        templates::{MapTimings, Matmul, MatmulTiming, ReduceTimings, ScanTimings, ShapeInfo},
         FlatmapTimings,
     };
 
-    use super::naive;
+    use super::{naive, parallel, topk};
 
     #[test]
     fn test_naive_attention() {
@@ -83,12 +126,17 @@ mod tests {
                     dot_latency: 1,
                     dot_ii: 1,
                     reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
                 },
                 crate::templates::MatmulBehavior::Buffered,
                 ShapeInfo {
                     m: SEQ_LEN,
                     n: SEQ_LEN,
                     k: DIM,
+                    batch: 1,
+                    broadcast: BatchBroadcast::None,
                 },
                 a_recv,
                 b_recv,
@@ -117,6 +165,7 @@ mod tests {
             AttentionConfig {
                 vocab_dim: DIM,
                 seq_len: SEQ_LEN,
+                query_rows: SEQ_LEN,
             },
             naive::NaiveConfig {
                 long_chan_size: LONG_DEPTH,
@@ -134,11 +183,260 @@ mod tests {
                     latency: 1,
                     reset_time: 0,
                 },
+                repeat_timings: RepeatTimings {
+                    initiation_interval: 0,
+                    latency: 1,
+                },
                 matmul_timings: MatmulTiming {
                     dot_latency: 1,
                     dot_ii: 1,
                     reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
+                },
+                pv_matmul: naive::PvMatmul::Streaming(MatmulBehavior::Buffered),
+            },
+        );
+        builder.add_child(ApproxCheckerContext::new(
+            || attn.into_iter(),
+            naive_attn,
+            |a, b| (a - b).abs() < 0.01,
+        ));
+
+        let executed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+        dbg!(executed.elapsed_cycles());
+    }
+
+    #[test]
+    fn test_systolic_naive_attention() {
+        const SEQ_LEN: usize = 64;
+        const DIM: usize = 4;
+        const SHORT_DEPTH: usize = 16;
+        const LONG_DEPTH: usize = SEQ_LEN + 2;
+        const GRID: SystolicGrid = SystolicGrid { rows: 4, cols: 4 };
+        const TIMING: MatmulTiming = MatmulTiming {
+            dot_latency: 1,
+            dot_ii: 1,
+            reset_time: 0,
+            vector_width: 1,
+            reduction_latency: 0,
+            batch_reset_time: 0,
+        };
+        let q = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let k = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let v = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let attn = compute_attention(q.view(), k.view(), v.view());
+
+        let mut builder = ProgramBuilder::default();
+
+        // Both matmuls run on systolic arrays, fed the same streams as the buffered Matmul.
+        let qkt_receiver = {
+            let (a_snd, a_recv) = builder.bounded(SHORT_DEPTH);
+            let (b_snd, b_recv) = builder.bounded(SHORT_DEPTH);
+            let (qkt_sender, qkt_receiver) = builder.bounded(SHORT_DEPTH);
+
+            builder.add_child(GeneratorContext::new(|| q.into_iter(), a_snd));
+            builder.add_child(GeneratorContext::new(
+                || {
+                    (0..SEQ_LEN)
+                        .flat_map(move |_| k.iter().copied().collect::<Vec<_>>().into_iter())
+                },
+                b_snd,
+            ));
+
+            builder.add_child(SystolicMatmul::new(
+                TIMING,
+                SystolicDataflow::OutputStationary,
+                GRID,
+                ShapeInfo {
+                    m: SEQ_LEN,
+                    n: SEQ_LEN,
+                    k: DIM,
+                    batch: 1,
+                    broadcast: BatchBroadcast::None,
+                },
+                a_recv,
+                b_recv,
+                qkt_sender,
+                |a, b, c: f64| a * b + c,
+            ));
+
+            qkt_receiver
+        };
+
+        let (v_snd, v_recv) = builder.bounded(SHORT_DEPTH);
+        builder.add_child(GeneratorContext::new(
+            || {
+                (0..SEQ_LEN)
+                    .flat_map(move |_| v.t().iter().copied().collect::<Vec<_>>().into_iter())
+            },
+            v_snd,
+        ));
+
+        let naive_attn = naive::naive(
+            &mut builder,
+            qkt_receiver,
+            v_recv,
+            AttentionConfig {
+                vocab_dim: DIM,
+                seq_len: SEQ_LEN,
+                query_rows: SEQ_LEN,
+            },
+            naive::NaiveConfig {
+                long_chan_size: LONG_DEPTH,
+                short_chan_depth: SHORT_DEPTH,
+                exp_timings: MapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                div_timings: MapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                sum_timings: ReduceTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
                 },
+                repeat_timings: RepeatTimings {
+                    initiation_interval: 0,
+                    latency: 1,
+                },
+                matmul_timings: TIMING,
+                pv_matmul: naive::PvMatmul::Systolic(SystolicDataflow::WeightStationary, GRID),
+            },
+        );
+        builder.add_child(ApproxCheckerContext::new(
+            || attn.into_iter(),
+            naive_attn,
+            |a, b| (a - b).abs() < 0.01,
+        ));
+
+        let executed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+        dbg!(executed.elapsed_cycles());
+    }
+
+    #[test]
+    fn test_vector_naive_attention() {
+        const SEQ_LEN: usize = 256;
+        const DIM: usize = 4;
+        const SHORT_DEPTH: usize = 16;
+        const WIDTH: usize = 8;
+        // The exp→div buffer holds vectors, so a row takes a W-th of the scalar depth.
+        const LONG_DEPTH: usize = SEQ_LEN / WIDTH + 2;
+        let q = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let k = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let v = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let attn = compute_attention(q.view(), k.view(), v.view());
+
+        let mut builder = ProgramBuilder::default();
+
+        // Assemble the matmul
+        let qkt_receiver = {
+            let (a_snd, a_recv) = builder.bounded(SHORT_DEPTH);
+            let (b_snd, b_recv) = builder.bounded(SHORT_DEPTH);
+            let (qkt_sender, qkt_receiver) = builder.bounded(SHORT_DEPTH);
+
+            builder.add_child(GeneratorContext::new(|| q.into_iter(), a_snd));
+            builder.add_child(GeneratorContext::new(
+                || {
+                    (0..SEQ_LEN)
+                        .flat_map(move |_| k.iter().copied().collect::<Vec<_>>().into_iter())
+                },
+                b_snd,
+            ));
+
+            builder.add_child(Matmul::new(
+                MatmulTiming {
+                    dot_latency: 1,
+                    dot_ii: 1,
+                    reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
+                },
+                crate::templates::MatmulBehavior::Buffered,
+                ShapeInfo {
+                    m: SEQ_LEN,
+                    n: SEQ_LEN,
+                    k: DIM,
+                    batch: 1,
+                    broadcast: BatchBroadcast::None,
+                },
+                a_recv,
+                b_recv,
+                qkt_sender,
+                |a, b, c: f64| a * b + c,
+            ));
+
+            qkt_receiver
+        };
+
+        let (v_snd, v_recv) = builder.bounded(SHORT_DEPTH);
+        builder.add_child(GeneratorContext::new(
+            || {
+                (0..SEQ_LEN)
+                    .flat_map(move |_| v.t().iter().copied().collect::<Vec<_>>().into_iter())
+            },
+            v_snd,
+        ));
+
+        let naive_attn = naive::vector_naive(
+            &mut builder,
+            qkt_receiver,
+            v_recv,
+            AttentionConfig {
+                vocab_dim: DIM,
+                seq_len: SEQ_LEN,
+                query_rows: SEQ_LEN,
+            },
+            naive::VectorNaiveConfig {
+                width: WIDTH,
+                long_chan_size: LONG_DEPTH,
+                short_chan_depth: SHORT_DEPTH,
+                pack_timings: PackTimings {
+                    initiation_interval: 0,
+                    latency: 1,
+                },
+                unpack_timings: PackTimings {
+                    initiation_interval: 0,
+                    latency: 0,
+                },
+                exp_timings: VectorTimings {
+                    lanes: WIDTH,
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                div_timings: VectorTimings {
+                    lanes: WIDTH,
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                sum_timings: ReduceTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+                repeat_timings: RepeatTimings {
+                    initiation_interval: 0,
+                    latency: 1,
+                },
+                matmul_timings: MatmulTiming {
+                    dot_latency: 1,
+                    dot_ii: 1,
+                    reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
+                },
+                pv_matmul: naive::PvMatmul::Streaming(MatmulBehavior::Buffered),
             },
         );
         builder.add_child(ApproxCheckerContext::new(
@@ -191,12 +489,17 @@ mod tests {
                     dot_latency: 1,
                     dot_ii: 1,
                     reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
                 },
                 crate::templates::MatmulBehavior::Buffered,
                 ShapeInfo {
                     m: SEQ_LEN,
                     n: SEQ_LEN,
                     k: DIM,
+                    batch: 1,
+                    broadcast: BatchBroadcast::None,
                 },
                 a_recv,
                 b_recv,
@@ -222,6 +525,7 @@ mod tests {
             AttentionConfig {
                 vocab_dim: DIM,
                 seq_len: SEQ_LEN,
+                query_rows: SEQ_LEN,
             },
             AgnosticConfig {
                 chan_depth: SHORT_DEPTH,
@@ -244,6 +548,8 @@ mod tests {
                     initiation_interval: 1,
                     latency: 1,
                 },
+                recurrence_latency: 1,
+                interleaved_rows: 1,
             },
         );
 
@@ -260,4 +566,374 @@ mod tests {
             .run(Default::default());
         dbg!(executed.elapsed_cycles());
     }
+
+    #[test]
+    fn test_topk_attention() {
+        const SEQ_LEN: usize = 256;
+        const DIM: usize = 4;
+        const SHORT_DEPTH: usize = 16;
+        const TOPK: usize = 32;
+        let q = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let k = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let v = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let mask = topk_mask(q.dot(&k.t()).view(), TOPK);
+        let attn = compute_masked_attention(q.view(), k.view(), v.view(), mask.view());
+
+        let mut builder = ProgramBuilder::default();
+
+        // Assemble the matmul
+        let qkt_receiver = {
+            let (a_snd, a_recv) = builder.bounded(SHORT_DEPTH);
+            let (b_snd, b_recv) = builder.bounded(SHORT_DEPTH);
+            let (qkt_sender, qkt_receiver) = builder.bounded(SHORT_DEPTH);
+
+            builder.add_child(GeneratorContext::new(|| q.into_iter(), a_snd));
+            builder.add_child(GeneratorContext::new(
+                || {
+                    (0..SEQ_LEN)
+                        .flat_map(move |_| k.iter().copied().collect::<Vec<_>>().into_iter())
+                },
+                b_snd,
+            ));
+
+            builder.add_child(Matmul::new(
+                MatmulTiming {
+                    dot_latency: 1,
+                    dot_ii: 1,
+                    reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
+                },
+                crate::templates::MatmulBehavior::Buffered,
+                ShapeInfo {
+                    m: SEQ_LEN,
+                    n: SEQ_LEN,
+                    k: DIM,
+                    batch: 1,
+                    broadcast: BatchBroadcast::None,
+                },
+                a_recv,
+                b_recv,
+                qkt_sender,
+                |a, b, c: f64| a * b + c,
+            ));
+
+            qkt_receiver
+        };
+
+        let topk_attn = topk::topk_attention(
+            &mut builder,
+            qkt_receiver,
+            v,
+            AttentionConfig {
+                vocab_dim: DIM,
+                seq_len: SEQ_LEN,
+                query_rows: SEQ_LEN,
+            },
+            topk::TopKConfig {
+                k: TOPK,
+                chan_depth: SHORT_DEPTH,
+                select_timings: TopKTimings {
+                    initiation_interval: 1,
+                    comparator_latency: 1,
+                    parallel_compare: true,
+                    reset_time: 0,
+                },
+                exp_timings: MapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                sum_timings: ReduceTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+                repeat_timings: RepeatTimings {
+                    initiation_interval: 0,
+                    latency: 1,
+                },
+                div_timings: MapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                gather_timings: FlatmapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                matmul_timings: MatmulTiming {
+                    dot_latency: 1,
+                    dot_ii: 1,
+                    reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
+                },
+            },
+        );
+
+        builder.add_child(ApproxCheckerContext::new(
+            || attn.into_iter(),
+            topk_attn,
+            |a, b| (a - b).abs() < 0.01,
+        ));
+
+        let executed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+        dbg!(executed.elapsed_cycles());
+    }
+
+    #[test]
+    fn test_parallel_attention() {
+        const SEQ_LEN: usize = 64;
+        const DIM: usize = 4;
+        const SHORT_DEPTH: usize = 16;
+        const LANES: usize = 4;
+        let q = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let k = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let v = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let attn = compute_attention(q.view(), k.view(), v.view());
+        let config = AttentionConfig {
+            vocab_dim: DIM,
+            seq_len: SEQ_LEN,
+            query_rows: SEQ_LEN,
+        };
+        let rows = parallel::lane_rows(config, LANES);
+
+        let pipelines = [
+            parallel::SoftmaxPipeline::Naive(naive::NaiveConfig {
+                long_chan_size: SEQ_LEN + 2,
+                short_chan_depth: SHORT_DEPTH,
+                exp_timings: MapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                div_timings: MapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                sum_timings: ReduceTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+                repeat_timings: RepeatTimings {
+                    initiation_interval: 0,
+                    latency: 1,
+                },
+                matmul_timings: MatmulTiming {
+                    dot_latency: 1,
+                    dot_ii: 1,
+                    reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
+                },
+                pv_matmul: naive::PvMatmul::Streaming(MatmulBehavior::Buffered),
+            }),
+            parallel::SoftmaxPipeline::Agnostic(AgnosticConfig {
+                chan_depth: SHORT_DEPTH,
+                max_config: ScanTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+                residual_config: ReduceTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+                prod_config: ReduceTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+                scale_config: FlatmapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+                recurrence_latency: 1,
+                interleaved_rows: 1,
+            }),
+        ];
+        for pipeline in pipelines {
+            let transposed = matches!(pipeline, parallel::SoftmaxPipeline::Naive(_));
+            let mut builder = ProgramBuilder::default();
+
+            let (a_snd, a_recv) = builder.bounded(SHORT_DEPTH);
+            let (b_snd, b_recv) = builder.bounded(SHORT_DEPTH);
+            let (qkt_sender, qkt_receiver) = builder.bounded(SHORT_DEPTH);
+            builder.add_child(GeneratorContext::new(|| q.iter().copied(), a_snd));
+            builder.add_child(GeneratorContext::new(
+                || (0..SEQ_LEN).flat_map(|_| k.iter().copied().collect::<Vec<_>>()),
+                b_snd,
+            ));
+            builder.add_child(Matmul::new(
+                MatmulTiming {
+                    dot_latency: 1,
+                    dot_ii: 1,
+                    reset_time: 0,
+                    vector_width: 1,
+                    reduction_latency: 0,
+                    batch_reset_time: 0,
+                },
+                MatmulBehavior::Buffered,
+                ShapeInfo {
+                    m: SEQ_LEN,
+                    n: SEQ_LEN,
+                    k: DIM,
+                    batch: 1,
+                    broadcast: BatchBroadcast::None,
+                },
+                a_recv,
+                b_recv,
+                qkt_sender,
+                |a, b, c: f64| a * b + c,
+            ));
+
+            // Every lane reads V once for each of its rows.
+            let v_receivers = (0..LANES)
+                .map(|_| {
+                    let (v_snd, v_recv) = builder.bounded(SHORT_DEPTH);
+                    let v = &v;
+                    builder.add_child(GeneratorContext::new(
+                        move || {
+                            (0..rows).flat_map(move |_| match transposed {
+                                true => v.t().iter().copied().collect::<Vec<_>>(),
+                                false => v.iter().copied().collect::<Vec<_>>(),
+                            })
+                        },
+                        v_snd,
+                    ));
+                    v_recv
+                })
+                .collect();
+
+            let parallel_attn = parallel::parallel_attention(
+                &mut builder,
+                qkt_receiver,
+                v_receivers,
+                config,
+                parallel::ParallelConfig {
+                    chan_depth: SHORT_DEPTH,
+                    lane_depth: SEQ_LEN,
+                    reorder_capacity: LANES * DIM,
+                    distribute_timings: DistributeTimings {
+                        initiation_interval: 1,
+                        latency: 1,
+                    },
+                    reorder_timings: ReorderTimings {
+                        initiation_interval: 1,
+                        latency: 1,
+                    },
+                },
+                pipeline,
+            );
+            builder.add_child(ApproxCheckerContext::new(
+                || attn.iter().copied(),
+                parallel_attn,
+                |a, b| (a - b).abs() < 0.01,
+            ));
+
+            let executed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+            dbg!(executed.elapsed_cycles());
+        }
+    }
+
+    #[test]
+    fn test_interleaved_agnostic_attention() {
+        const SEQ_LEN: usize = 64;
+        const DIM: usize = 2;
+        const SHORT_DEPTH: usize = 16;
+        const RECURRENCE_LATENCY: u64 = 8;
+        let q = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let k = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let v = ArcArray::from_shape_simple_fn([SEQ_LEN, DIM], fastrand::f64);
+        let qkt = q.dot(&k.t());
+        let attn = compute_attention(q.view(), k.view(), v.view());
+
+        let mut elapsed = vec![];
+        for rows in [1, 2, 4] {
+            let mut builder = ProgramBuilder::default();
+            let (qkt_snd, qkt_rcv) = builder.bounded(SHORT_DEPTH);
+            builder.add_child(GeneratorContext::new(|| qkt.iter().copied(), qkt_snd));
+            let qkt_rcv = match rows {
+                1 => qkt_rcv,
+                _ => interleave_rows(&mut builder, qkt_rcv, SEQ_LEN, rows),
+            };
+            // Every group of rows goes through V once, with each row of V repeated per query row.
+            let (v_snd, v_rcv) = builder.bounded(SHORT_DEPTH);
+            builder.add_child(GeneratorContext::new(
+                || {
+                    let v = v.clone();
+                    (0..SEQ_LEN / rows).flat_map(move |_| {
+                        v.outer_iter()
+                            .flat_map(|row| std::iter::repeat_n(row.to_vec(), rows).flatten())
+                            .collect::<Vec<_>>()
+                            .into_iter()
+                    })
+                },
+                v_snd,
+            ));
+
+            let agnostic_attn = agnostic_attention(
+                &mut builder,
+                qkt_rcv,
+                v_rcv,
+                AttentionConfig {
+                    vocab_dim: DIM,
+                    seq_len: SEQ_LEN,
+                    query_rows: SEQ_LEN,
+                },
+                AgnosticConfig {
+                    chan_depth: SHORT_DEPTH,
+                    max_config: ScanTimings {
+                        initiation_interval: 1,
+                        latency: 1,
+                        reset_time: 0,
+                    },
+                    residual_config: ReduceTimings {
+                        initiation_interval: 1,
+                        latency: 1,
+                        reset_time: 0,
+                    },
+                    prod_config: ReduceTimings {
+                        initiation_interval: 1,
+                        latency: 1,
+                        reset_time: 0,
+                    },
+                    scale_config: FlatmapTimings {
+                        initiation_interval: 1,
+                        latency: 1,
+                    },
+                    recurrence_latency: RECURRENCE_LATENCY,
+                    interleaved_rows: rows,
+                },
+            );
+            builder.add_child(ApproxCheckerContext::new(
+                || attn.clone().into_iter(),
+                agnostic_attn,
+                |a, b| (a - b).abs() < 0.01,
+            ));
+
+            let executed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+            elapsed.push(executed.elapsed_cycles().unwrap());
+        }
+        let gains: Vec<_> = elapsed
+            .iter()
+            .map(|cycles| elapsed[0] as f64 / *cycles as f64)
+            .collect();
+        dbg!(&elapsed, &gains);
+        // Each doubling of the interleaved rows roughly halves the stalls on the recurrence.
+        assert!(gains[1] > 1.5);
+        assert!(gains[2] > gains[1]);
+    }
 }
@@ -4,13 +4,77 @@ use crate::templates::*;
 
 use super::AttentionConfig;
 
+#[derive(Debug, Clone, Copy)]
 pub struct NaiveConfig {
     pub long_chan_size: usize,
     pub short_chan_depth: usize,
     pub exp_timings: MapTimings,
     pub div_timings: MapTimings,
     pub sum_timings: ReduceTimings,
+    pub repeat_timings: RepeatTimings,
     pub matmul_timings: MatmulTiming,
+    pub pv_matmul: PvMatmul,
+}
+
+/// The unit computing P·V. It has to consume P in the row-major order the softmax produces it.
+#[derive(Debug, Clone, Copy)]
+pub enum PvMatmul {
+    /// Buffered or WeightStationary (V arrives transposed) or RowWise (V arrives as-is)
+    Streaming(MatmulBehavior),
+    /// A systolic array, reading V transposed like Buffered
+    Systolic(SystolicDataflow, SystolicGrid),
+}
+
+fn pv_matmul<'a, T: DAMType + num::Float + 'a>(
+    builder: &mut ProgramBuilder<'a>,
+    pv_matmul: PvMatmul,
+    timings: MatmulTiming,
+    config: AttentionConfig,
+    p_receiver: Receiver<T>,
+    v_receiver: Receiver<T>,
+    output_sender: Sender<T>,
+) {
+    let shape = ShapeInfo {
+        m: config.query_rows,
+        n: config.vocab_dim,
+        k: config.seq_len,
+        batch: 1,
+        broadcast: BatchBroadcast::None,
+    };
+    match pv_matmul {
+        PvMatmul::Streaming(behavior) => {
+            assert!(
+                matches!(
+                    behavior,
+                    MatmulBehavior::Buffered
+                        | MatmulBehavior::RowWise
+                        | MatmulBehavior::WeightStationary { .. }
+                ),
+                "The P·V matmul must consume P in row-major order"
+            );
+            builder.add_child(Matmul::new(
+                timings,
+                behavior,
+                shape,
+                p_receiver,
+                v_receiver,
+                output_sender,
+                |a, b, c| (a * b) + c,
+            ));
+        }
+        PvMatmul::Systolic(dataflow, grid) => {
+            builder.add_child(SystolicMatmul::new(
+                timings,
+                dataflow,
+                grid,
+                shape,
+                p_receiver,
+                v_receiver,
+                output_sender,
+                |a, b, c| (a * b) + c,
+            ));
+        }
+    }
 }
 
 pub fn naive<'a, T: DAMType + num::Float>(
@@ -54,6 +118,7 @@ where
             targets: vec![rep_to_div_snd],
         },
         config.seq_len,
+        naive_config.repeat_timings,
     ));
 
     let (div_to_mm_snd, div_to_mm_rcv) = builder.bounded(naive_config.short_chan_depth);
@@ -70,19 +135,139 @@ where
     // take the product of p_ij with v to get the result.
     let (output_snd, output_rcv) = builder.bounded(naive_config.short_chan_depth);
 
-    builder.add_child(Matmul::new(
+    pv_matmul(
+        builder,
+        naive_config.pv_matmul,
         naive_config.matmul_timings,
-        MatmulBehavior::Buffered,
-        ShapeInfo {
-            m: config.seq_len,
-            n: config.vocab_dim,
-            k: config.seq_len,
-        },
+        config,
         div_to_mm_rcv,
         v_receiver,
         output_snd,
-        |a, b, c| (a * b) + c,
+    );
+
+    output_rcv
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VectorNaiveConfig {
+    /// Elements of a row that every stage processes at once
+    pub width: usize,
+    /// Depth of the exp→div channel, in vectors. A whole row needs seq_len / width.
+    pub long_chan_size: usize,
+    pub short_chan_depth: usize,
+    pub pack_timings: PackTimings,
+    /// Splitting each divided vector of P back into scalars for the P·V matmul
+    pub unpack_timings: PackTimings,
+    pub exp_timings: VectorTimings,
+    pub div_timings: VectorTimings,
+    /// Per vector, covering both the adder tree and the running row sum
+    pub sum_timings: ReduceTimings,
+    /// Per copy of the row sum, one for every vector of the row
+    pub repeat_timings: RepeatTimings,
+    /// The P·V matmul still takes P as scalars, so it only keeps up with the divider when its
+    /// `vector_width` is at least `width`.
+    pub matmul_timings: MatmulTiming,
+    pub pv_matmul: PvMatmul,
+}
+
+/// The [naive] pipeline with every row split into vectors of `width` elements, so exp, sum
+/// and divide each handle `width` elements per initiation interval.
+pub fn vector_naive<'a, T>(
+    builder: &mut ProgramBuilder<'a>,
+    qkt_receiver: Receiver<T>,
+    v_receiver: Receiver<T>,
+    config: AttentionConfig,
+    naive_config: VectorNaiveConfig,
+) -> Receiver<T>
+where
+    T: DAMType + num::Float + 'a,
+{
+    assert!(
+        config.seq_len.is_multiple_of(naive_config.width),
+        "A row of {} elements can't be split into vectors of {}",
+        config.seq_len,
+        naive_config.width
+    );
+    let vectors_per_row = config.seq_len / naive_config.width;
+
+    let (qkt_vec_snd, qkt_vec_rcv) = builder.bounded(naive_config.short_chan_depth);
+    builder.add_child(Pack::new(
+        qkt_receiver,
+        BroadcastSender {
+            targets: vec![qkt_vec_snd],
+        },
+        naive_config.width,
+        naive_config.pack_timings,
     ));
 
+    let (exp_to_div_snd, exp_to_div_rcv) = builder.bounded(naive_config.long_chan_size);
+    let (exp_to_sum_snd, exp_to_sum_rcv) = builder.bounded(naive_config.short_chan_depth);
+    let (sum_to_rep_snd, sum_to_rep_rcv) = builder.bounded(naive_config.short_chan_depth);
+    let (rep_to_div_snd, rep_to_div_rcv) = builder.bounded(naive_config.short_chan_depth);
+    builder.add_child(VectorMap::new(
+        vec![qkt_vec_rcv],
+        BroadcastSender {
+            targets: vec![exp_to_div_snd, exp_to_sum_snd],
+        },
+        |qkt: &[T]| qkt[0].exp(),
+        naive_config.exp_timings,
+    ));
+
+    builder.add_child(Reduce::new(
+        vectors_per_row,
+        exp_to_sum_rcv,
+        sum_to_rep_snd,
+        |new: Vector<T>, cur: Option<T>| {
+            let partial = new.value.into_iter().fold(T::zero(), |a, b| a + b);
+            match cur {
+                Some(x) => partial + x,
+                None => partial,
+            }
+        },
+        naive_config.sum_timings,
+    ));
+
+    builder.add_child(Repeat::new(
+        sum_to_rep_rcv,
+        BroadcastSender {
+            targets: vec![rep_to_div_snd],
+        },
+        vectors_per_row,
+        naive_config.repeat_timings,
+    ));
+
+    let (div_to_unpack_snd, div_to_unpack_rcv) = builder.bounded(naive_config.short_chan_depth);
+    builder.add_child(VectorScalarMap::new(
+        exp_to_div_rcv,
+        rep_to_div_rcv,
+        BroadcastSender {
+            targets: vec![div_to_unpack_snd],
+        },
+        |exp: T, sum: &T| exp / *sum,
+        naive_config.div_timings,
+    ));
+
+    // The matmul takes P a scalar at a time, but can consume a whole vector per cycle.
+    let (div_to_mm_snd, div_to_mm_rcv) =
+        builder.bounded(naive_config.short_chan_depth.max(naive_config.width));
+    builder.add_child(Unpack::new(
+        div_to_unpack_rcv,
+        BroadcastSender {
+            targets: vec![div_to_mm_snd],
+        },
+        naive_config.unpack_timings,
+    ));
+
+    let (output_snd, output_rcv) = builder.bounded(naive_config.short_chan_depth);
+    pv_matmul(
+        builder,
+        naive_config.pv_matmul,
+        naive_config.matmul_timings,
+        config,
+        div_to_mm_rcv,
+        v_receiver,
+        output_snd,
+    );
+
     output_rcv
 }
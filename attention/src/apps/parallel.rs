@@ -0,0 +1,118 @@
+use dam::{context_tools::*, simulation::ProgramBuilder};
+
+use crate::templates::*;
+
+use super::{
+    agnostic::{agnostic_attention, AgnosticConfig},
+    naive::{naive, vector_naive, NaiveConfig, VectorNaiveConfig},
+    AttentionConfig,
+};
+
+/// The softmax pipeline that every lane runs.
+#[derive(Debug, Clone, Copy)]
+pub enum SoftmaxPipeline {
+    Naive(NaiveConfig),
+    VectorNaive(VectorNaiveConfig),
+    Agnostic(AgnosticConfig),
+}
+
+impl SoftmaxPipeline {
+    pub fn build<'a, T>(
+        self,
+        builder: &mut ProgramBuilder<'a>,
+        qkt_receiver: Receiver<T>,
+        v_receiver: Receiver<T>,
+        config: AttentionConfig,
+    ) -> Receiver<T>
+    where
+        T: DAMType + num::Float + 'a,
+    {
+        match self {
+            SoftmaxPipeline::Naive(naive_config) => {
+                naive(builder, qkt_receiver, v_receiver, config, naive_config)
+            }
+            SoftmaxPipeline::VectorNaive(naive_config) => {
+                vector_naive(builder, qkt_receiver, v_receiver, config, naive_config)
+            }
+            SoftmaxPipeline::Agnostic(agnostic_config) => {
+                agnostic_attention(builder, qkt_receiver, v_receiver, config, agnostic_config)
+            }
+        }
+    }
+
+    /// Query rows whose scores arrive interleaved, and so have to stay on the same lane.
+    pub fn interleaved_rows(&self) -> usize {
+        match self {
+            SoftmaxPipeline::Agnostic(agnostic_config) => agnostic_config.interleaved_rows,
+            _ => 1,
+        }
+    }
+}
+
+pub struct ParallelConfig {
+    pub chan_depth: usize,
+    /// Depth of the channel into each lane. With room for a whole row, the distributor can move
+    /// on to the next lane while this one works through it.
+    pub lane_depth: usize,
+    /// Elements of finished rows that the reorder buffer can hold while an earlier row is
+    /// still being computed
+    pub reorder_capacity: usize,
+    pub distribute_timings: DistributeTimings,
+    pub reorder_timings: ReorderTimings,
+}
+
+/// Query rows of each attention matrix that go to a lane.
+pub fn lane_rows(config: AttentionConfig, lanes: usize) -> usize {
+    assert!(
+        config.query_rows.is_multiple_of(lanes),
+        "{} query rows can't be split evenly across {lanes} lanes",
+        config.query_rows
+    );
+    config.query_rows / lanes
+}
+
+/// Runs one copy of the pipeline per entry of `v_receivers`, with query row i going to lane
+/// i % P, and puts the output rows back in order. Groups of interleaved rows are kept together.
+/// `v_receivers[lane]` carries the V that the lane's pipeline consumes for its [lane_rows] rows.
+pub fn parallel_attention<'a, T>(
+    builder: &mut ProgramBuilder<'a>,
+    qkt_receiver: Receiver<T>,
+    v_receivers: Vec<Receiver<T>>,
+    config: AttentionConfig,
+    parallel_config: ParallelConfig,
+    pipeline: SoftmaxPipeline,
+) -> Receiver<T>
+where
+    T: DAMType + num::Float + 'a,
+{
+    let lanes = v_receivers.len();
+    let group = pipeline.interleaved_rows();
+    let lane_config = AttentionConfig {
+        query_rows: lane_rows(config, lanes),
+        ..config
+    };
+
+    let mut qkt_senders = vec![];
+    let mut outputs = vec![];
+    for v_receiver in v_receivers {
+        let (qkt_snd, qkt_rcv) = builder.bounded(parallel_config.lane_depth);
+        qkt_senders.push(qkt_snd);
+        outputs.push(pipeline.build(builder, qkt_rcv, v_receiver, lane_config));
+    }
+    builder.add_child(Distributor::new(
+        qkt_receiver,
+        qkt_senders,
+        DistributePolicy::Blocks(config.seq_len * group),
+        parallel_config.distribute_timings,
+    ));
+
+    let (output_snd, output_rcv) = builder.bounded(parallel_config.chan_depth);
+    builder.add_child(ReorderBuffer::new(
+        outputs,
+        output_snd,
+        config.vocab_dim * group,
+        parallel_config.reorder_capacity,
+        parallel_config.reorder_timings,
+    ));
+    output_rcv
+}
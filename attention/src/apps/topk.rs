@@ -0,0 +1,163 @@
+use dam::{context_tools::*, simulation::ProgramBuilder};
+use ndarray::ArcArray2;
+
+use crate::templates::*;
+
+use super::AttentionConfig;
+
+pub struct TopKConfig {
+    /// Number of scores kept per query row
+    pub k: usize,
+    pub chan_depth: usize,
+    pub select_timings: TopKTimings,
+    pub exp_timings: MapTimings,
+    pub sum_timings: ReduceTimings,
+    pub repeat_timings: RepeatTimings,
+    pub div_timings: MapTimings,
+    pub gather_timings: FlatmapTimings,
+    pub matmul_timings: MatmulTiming,
+}
+
+/// Attention where each query row only attends to its k largest scores.
+/// The selected scores go through a naive softmax, while their indices are used to
+/// gather the matching rows of V (held in memory) for the P·V product.
+pub fn topk_attention<'a, T>(
+    builder: &mut ProgramBuilder<'a>,
+    qkt_receiver: Receiver<T>,
+    v: ArcArray2<T>,
+    config: AttentionConfig,
+    topk_config: TopKConfig,
+) -> Receiver<T>
+where
+    T: DAMType + num::Float + 'a,
+{
+    let k = topk_config.k;
+    assert!(
+        k <= config.seq_len,
+        "Can't keep {} scores out of a row of {}",
+        k,
+        config.seq_len
+    );
+    let (sel_to_exp_snd, sel_to_exp_rcv) = builder.bounded(topk_config.chan_depth);
+    let (sel_to_gather_snd, sel_to_gather_rcv) = builder.bounded(topk_config.chan_depth);
+
+    builder.add_child(TopK::new(
+        k,
+        config.seq_len,
+        qkt_receiver,
+        BroadcastSender {
+            targets: vec![sel_to_exp_snd, sel_to_gather_snd],
+        },
+        |new: &T, old: &T| new > old,
+        topk_config.select_timings,
+    ));
+
+    // The exp values have to wait for the whole row sum, so this channel holds k elements.
+    let (exp_to_div_snd, exp_to_div_rcv) = builder.bounded(k + topk_config.chan_depth);
+    let (exp_to_sum_snd, exp_to_sum_rcv) = builder.bounded(topk_config.chan_depth);
+    let (sum_to_rep_snd, sum_to_rep_rcv) = builder.bounded(topk_config.chan_depth);
+    let (rep_to_div_snd, rep_to_div_rcv) = builder.bounded(topk_config.chan_depth);
+
+    // Map over e^x of the selected scores
+    builder.add_child(Map::new(
+        vec![sel_to_exp_rcv],
+        BroadcastSender {
+            targets: vec![exp_to_div_snd, exp_to_sum_snd],
+        },
+        |selected: &[Pair<usize, T>]| selected[0].1.exp(),
+        topk_config.exp_timings,
+    ));
+
+    builder.add_child(Reduce::new(
+        k,
+        exp_to_sum_rcv,
+        sum_to_rep_snd,
+        |new, cur| match cur {
+            Some(x) => new + x,
+            None => new,
+        },
+        topk_config.sum_timings,
+    ));
+
+    builder.add_child(Repeat::new(
+        sum_to_rep_rcv,
+        BroadcastSender {
+            targets: vec![rep_to_div_snd],
+        },
+        k,
+        topk_config.repeat_timings,
+    ));
+
+    let (div_to_mm_snd, div_to_mm_rcv) = builder.bounded(topk_config.chan_depth);
+    builder.add_child(Map::new(
+        vec![exp_to_div_rcv, rep_to_div_rcv],
+        BroadcastSender {
+            targets: vec![div_to_mm_snd],
+        },
+        |args| args[0] / args[1],
+        topk_config.div_timings,
+    ));
+
+    // Collect the selected indices of a row so that V can be gathered column by column.
+    let (idx_snd, idx_rcv) = builder.bounded(topk_config.chan_depth);
+    builder.add_child(Map::new(
+        vec![sel_to_gather_rcv],
+        BroadcastSender {
+            targets: vec![idx_snd],
+        },
+        |args: &[Pair<usize, T>]| args[0].0,
+        MapTimings {
+            initiation_interval: 1,
+            latency: 0,
+        },
+    ));
+    let (idx_vec_snd, idx_vec_rcv) = builder.bounded(topk_config.chan_depth);
+    builder.add_child(Pack::new(
+        idx_rcv,
+        BroadcastSender {
+            targets: vec![idx_vec_snd],
+        },
+        k,
+        PackTimings {
+            initiation_interval: 1,
+            latency: 1,
+        },
+    ));
+
+    // Emit the selected rows of V transposed, which is the order Matmul expects for its right operand.
+    let (gather_snd, gather_rcv) = builder.bounded(topk_config.chan_depth);
+    builder.add_child(Flatmap::new(
+        vec![idx_vec_rcv],
+        BroadcastSender {
+            targets: vec![gather_snd],
+        },
+        move |mut packed| {
+            let rows = packed.pop().unwrap().value;
+            let v = &v;
+            (0..config.vocab_dim)
+                .flat_map(|col| rows.iter().map(move |&row| v[[row, col]]))
+                .collect::<Vec<_>>()
+                .into_iter()
+        },
+        topk_config.gather_timings,
+    ));
+
+    let (output_snd, output_rcv) = builder.bounded(topk_config.chan_depth);
+    builder.add_child(Matmul::new(
+        topk_config.matmul_timings,
+        MatmulBehavior::Buffered,
+        ShapeInfo {
+            m: config.query_rows,
+            n: config.vocab_dim,
+            k,
+            batch: 1,
+            broadcast: BatchBroadcast::None,
+        },
+        div_to_mm_rcv,
+        gather_rcv,
+        output_snd,
+        |a, b, c| (a * b) + c,
+    ));
+
+    output_rcv
+}
@@ -1,16 +1,22 @@
 pub mod apps;
 pub mod templates;
 pub mod utils;
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use dam::{
     simulation::{ProgramBuilder, RunMode, RunOptionsBuilder},
     utility_contexts::*,
 };
 use itertools::izip;
-use ndarray::ArcArray;
+use ndarray::{ArcArray, ArcArray2};
 
 use crate::{
-    apps::{agnostic::AgnosticConfig, compute_attention, AttentionConfig},
+    apps::{
+        agnostic::{interleave_rows, AgnosticConfig},
+        compute_attention,
+        naive::PvMatmul,
+        parallel::{lane_rows, parallel_attention, ParallelConfig, SoftmaxPipeline},
+        AttentionConfig,
+    },
     templates::*,
 };
 
@@ -42,6 +48,45 @@ struct CommandLineInterface {
     /// Limit the number of worker threads
     #[arg(long)]
     workers: Option<usize>,
+
+    /// Capacity of the on-chip weight buffer. If set, the QK^T matmul reads K only once per batch
+    /// when it fits, and otherwise reloads it a buffer at a time for every query row.
+    #[arg(long)]
+    weight_buffer: Option<usize>,
+
+    /// Cycles to load one element into the weight buffer
+    #[arg(long, default_value_t = 1)]
+    weight_load_ii: u64,
+
+    /// Run the QK^T matmul on a systolic array with this dataflow.
+    /// Also the array used by `--pv-dataflow systolic`.
+    #[arg(long, value_enum)]
+    systolic: Option<SystolicArg>,
+
+    /// Rows of PEs in the systolic array
+    #[arg(long, default_value_t = 16)]
+    systolic_rows: usize,
+
+    /// Columns of PEs in the systolic array
+    #[arg(long, default_value_t = 16)]
+    systolic_cols: usize,
+
+    /// Capacity of the SRAM that transposes V for the P·V matmul.
+    /// If not set, V is transposed for free by the generator.
+    #[arg(long)]
+    transpose_buffer: Option<usize>,
+
+    /// Cycles per element on each port of the transpose buffer
+    #[arg(long, default_value_t = 1)]
+    transpose_ii: u64,
+
+    /// Copies of the softmax pipeline that query rows are split across
+    #[arg(long, default_value_t = 1)]
+    lanes: usize,
+
+    /// Elements the reorder buffer behind the lanes can hold. Defaults to one row per lane.
+    #[arg(long)]
+    reorder_buffer: Option<usize>,
 }
 
 #[derive(Subcommand, Debug, Copy, Clone)]
@@ -50,20 +95,52 @@ enum Implementation {
         #[arg(long)]
         short_depth: usize,
 
+        /// Depth of the exp→div channel, in vectors of --width elements
         #[arg(long)]
         long_depth: usize,
 
+        /// Elements of a row that exp, sum and divide process at once
+        #[arg(long, default_value_t = 1)]
+        width: usize,
+
         #[arg(long, default_value_t = 1)]
         exp_ii: u64,
 
         #[arg(long, default_value_t = 1)]
         exp_latency: u64,
 
+        /// With --width above 1 this is per vector, adder tree included
         #[arg(long, default_value_t = 1)]
         sum_ii: u64,
 
+        /// With --width above 1 this is per vector, adder tree included
         #[arg(long, default_value_t = 1)]
         sum_latency: u64,
+
+        /// Cycles between vectors assembled from the scores, with --width above 1
+        #[arg(long, default_value_t = 0)]
+        pack_ii: u64,
+
+        #[arg(long, default_value_t = 1)]
+        pack_latency: u64,
+
+        /// Cycles between scalars split off a vector of P for the P·V matmul, with --width above 1
+        #[arg(long, default_value_t = 0)]
+        unpack_ii: u64,
+
+        #[arg(long, default_value_t = 0)]
+        unpack_latency: u64,
+
+        /// Cycles between copies of the row sum sent to the divider
+        #[arg(long, default_value_t = 0)]
+        repeat_ii: u64,
+
+        #[arg(long, default_value_t = 1)]
+        repeat_latency: u64,
+
+        /// Dataflow of the P·V matmul
+        #[arg(long, value_enum, default_value_t = PvDataflow::Buffered)]
+        pv_dataflow: PvDataflow,
     },
     Agnostic {
         #[arg(long)]
@@ -86,9 +163,49 @@ enum Implementation {
 
         #[arg(long, default_value_t = 1)]
         vector_prod_latency: u64,
+
+        /// Latency of the running max and sum accumulators
+        #[arg(long, default_value_t = 1)]
+        recurrence_latency: u64,
+
+        /// Query rows interleaved through the running max and sum, hiding their latency
+        /// Above 1, the speedup over a single row is reported as well
+        #[arg(long, default_value_t = 1)]
+        interleave: usize,
     },
 }
 
+/// Matmul behaviors that consume the softmax output (P) in row-major order.
+#[derive(ValueEnum, Debug, Copy, Clone)]
+enum PvDataflow {
+    Buffered,
+    RowWise,
+    /// Requires --weight-buffer
+    WeightStationary,
+    /// Requires --systolic
+    Systolic,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone)]
+enum SystolicArg {
+    /// Output-stationary
+    Output,
+    /// Weight-stationary
+    Weight,
+    /// Input-stationary
+    Input,
+}
+
+impl From<SystolicArg> for SystolicDataflow {
+    fn from(value: SystolicArg) -> Self {
+        match value {
+            SystolicArg::Output => SystolicDataflow::OutputStationary,
+            SystolicArg::Weight => SystolicDataflow::WeightStationary,
+            SystolicArg::Input => SystolicDataflow::InputStationary,
+        }
+    }
+}
+
 #[derive(Debug, Args)]
 struct CommonTimings {
     /// Matmul initiation interval
@@ -99,6 +216,14 @@ struct CommonTimings {
     #[arg(long, default_value_t = 1)]
     matmul_latency: u64,
 
+    /// Elements of K consumed by the matmul per initiation interval
+    #[arg(long, default_value_t = 1)]
+    matmul_vector_width: usize,
+
+    /// Latency of the matmul adder tree
+    #[arg(long, default_value_t = 0)]
+    matmul_reduction_latency: u64,
+
     #[arg(long, default_value_t = 1)]
     div_ii: u64,
 
@@ -129,14 +254,127 @@ fn main() {
 
     println!("Took {:?} to generate random values", gen_start.elapsed());
 
-    let short_depth = match args.mode {
+    let cycles = simulate(&args, args.mode, &q_matrices, &k_matrices, &v_matrices);
+    println!("Elapsed Cycles: {}", cycles);
+
+    // Report the gain from interleaving against the same pipeline taking one row at a time.
+    if let Implementation::Agnostic { interleave, .. } = args.mode {
+        if interleave > 1 {
+            let mut baseline_mode = args.mode;
+            if let Implementation::Agnostic { interleave, .. } = &mut baseline_mode {
+                *interleave = 1;
+            }
+            println!("Running again without interleaving");
+            let baseline = simulate(&args, baseline_mode, &q_matrices, &k_matrices, &v_matrices);
+            println!(
+                "Interleaving {} rows: {} cycles against {} for 1 row, a {:.2}x speedup",
+                interleave,
+                cycles,
+                baseline,
+                baseline as f64 / cycles as f64
+            );
+        }
+    }
+}
+
+/// Builds and runs the whole attention pipeline with the given mode, returning the elapsed cycles.
+fn simulate(
+    args: &CommandLineInterface,
+    mode: Implementation,
+    q_matrices: &[ArcArray2<f32>],
+    k_matrices: &[ArcArray2<f32>],
+    v_matrices: &[ArcArray2<f32>],
+) -> u64 {
+    let short_depth = match mode {
         Implementation::Naive { short_depth, .. } => short_depth,
         Implementation::Agnostic { channel_depth, .. } => channel_depth,
     };
 
+    let weight_stationary = args
+        .weight_buffer
+        .map(|capacity| MatmulBehavior::WeightStationary {
+            capacity,
+            load_ii: args.weight_load_ii,
+        });
+    let qkt_shape = ShapeInfo {
+        m: args.length,
+        n: args.length,
+        k: args.dim,
+        batch: 1,
+        broadcast: BatchBroadcast::None,
+    };
+    let systolic = args.systolic.map(|dataflow| {
+        (
+            SystolicDataflow::from(dataflow),
+            SystolicGrid {
+                rows: args.systolic_rows,
+                cols: args.systolic_cols,
+            },
+        )
+    });
+    assert!(
+        weight_stationary.is_none() || systolic.is_none(),
+        "The QK^T matmul can't use both --weight-buffer and --systolic"
+    );
+    // A systolic array reads the same streams as the buffered matmul.
+    let qkt_behavior = weight_stationary.unwrap_or(MatmulBehavior::Buffered);
+    // Unless all of K fits in the weight buffer, it is re-read for every query row.
+    let k_repeats = qkt_behavior.right_passes(&qkt_shape);
+
+    let config = AttentionConfig {
+        vocab_dim: args.dim,
+        seq_len: args.length,
+        query_rows: args.length,
+    };
+    // Each lane reads its own copy of V, for its share of the query rows.
+    let rows = lane_rows(config, args.lanes);
+    let pv_shape = ShapeInfo {
+        m: rows,
+        n: args.dim,
+        k: args.length,
+        batch: 1,
+        broadcast: BatchBroadcast::None,
+    };
+    let (v_repeats, v_transposed) = match mode {
+        Implementation::Naive {
+            pv_dataflow: PvDataflow::Buffered | PvDataflow::Systolic,
+            ..
+        } => (rows, true),
+        Implementation::Naive {
+            pv_dataflow: PvDataflow::WeightStationary,
+            ..
+        } => (
+            weight_stationary.map_or(1, |behavior| behavior.right_passes(&pv_shape)),
+            true,
+        ),
+        Implementation::Naive {
+            pv_dataflow: PvDataflow::RowWise,
+            ..
+        }
+        | Implementation::Agnostic { .. } => (rows, false),
+    };
+
+    let interleave = match mode {
+        Implementation::Agnostic { interleave, .. } => interleave,
+        _ => 1,
+    };
+    assert!(
+        rows.is_multiple_of(interleave),
+        "{rows} query rows per lane can't be interleaved in groups of {interleave}"
+    );
+
+    // A transpose buffer takes V row-major and transposes it in hardware.
+    assert!(
+        args.transpose_buffer.is_none() || v_transposed,
+        "--transpose-buffer only applies to naive pipelines whose P·V dataflow reads V transposed \
+         (buffered, weight-stationary or systolic)"
+    );
+    let transpose_buffer = args.transpose_buffer;
+    let generate_transposed = v_transposed && transpose_buffer.is_none();
+
     let mut builder = ProgramBuilder::default();
 
-    let (qkt_receiver, v_receiver) = {
+    let (qkt_receiver, v_receivers) = {
         let (qkt_sender, qkt_receiver) = builder.bounded(short_depth);
 
         let (a_snd, a_recv) = builder.bounded(short_depth);
@@ -148,77 +386,162 @@ fn main() {
         ));
         builder.add_child(GeneratorContext::new(
             || {
-                k_matrices.iter().flat_map(|mat_b| {
-                    (0..args.length)
+                k_matrices.iter().flat_map(move |mat_b| {
+                    (0..k_repeats)
                         .flat_map(move |_| mat_b.iter().copied().collect::<Vec<_>>().into_iter())
                 })
             },
             b_snd,
         ));
 
-        builder.add_child(Matmul::new(
-            MatmulTiming {
-                dot_latency: args.common.matmul_latency,
-                dot_ii: args.common.matmul_ii,
-                reset_time: args.common.reset_time,
-            },
-            crate::templates::MatmulBehavior::Buffered,
-            ShapeInfo {
-                m: args.length,
-                n: args.length,
-                k: args.dim,
-            },
-            a_recv,
-            b_recv,
-            qkt_sender,
-            |a, b, c| a * b + c,
-        ));
-
-        let (v_snd, v_recv) = builder.bounded(short_depth);
-        builder.add_child(GeneratorContext::new(
-            || {
-                v_matrices.iter().flat_map(|v| {
-                    (0..args.length).flat_map(move |_| match args.mode {
-                        Implementation::Naive { .. } => {
-                            v.t().iter().copied().collect::<Vec<_>>().into_iter()
-                        }
-                        Implementation::Agnostic { .. } => {
-                            v.iter().copied().collect::<Vec<_>>().into_iter()
-                        }
-                    })
-                })
-            },
-            v_snd,
-        ));
-
-        (qkt_receiver, v_recv)
-    };
+        let qkt_timings = MatmulTiming {
+            dot_latency: args.common.matmul_latency,
+            dot_ii: args.common.matmul_ii,
+            reset_time: args.common.reset_time,
+            vector_width: args.common.matmul_vector_width,
+            reduction_latency: args.common.matmul_reduction_latency,
+            batch_reset_time: 0,
+        };
+        match systolic {
+            Some((dataflow, grid)) => builder.add_child(SystolicMatmul::new(
+                qkt_timings,
+                dataflow,
+                grid,
+                qkt_shape,
+                a_recv,
+                b_recv,
+                qkt_sender,
+                |a, b, c| a * b + c,
+            )),
+            None => builder.add_child(Matmul::new(
+                qkt_timings,
+                qkt_behavior,
+                qkt_shape,
+                a_recv,
+                b_recv,
+                qkt_sender,
+                |a, b, c| a * b + c,
+            )),
+        }
 
-    let config = AttentionConfig {
-        vocab_dim: args.dim,
-        seq_len: args.length,
+        let v_receivers = (0..args.lanes)
+            .map(|_| {
+                let (v_snd, v_recv) = builder.bounded(short_depth);
+                builder.add_child(GeneratorContext::new(
+                    || {
+                        v_matrices.iter().flat_map(move |v| {
+                            (0..v_repeats / interleave).flat_map(move |_| {
+                                match generate_transposed {
+                                    true => v.t().iter().copied().collect::<Vec<_>>().into_iter(),
+                                    // Interleaved rows each need every row of V in turn.
+                                    false => v
+                                        .outer_iter()
+                                        .flat_map(|row| {
+                                            std::iter::repeat_n(row.to_vec(), interleave).flatten()
+                                        })
+                                        .collect::<Vec<_>>()
+                                        .into_iter(),
+                                }
+                            })
+                        })
+                    },
+                    v_snd,
+                ));
+
+                match transpose_buffer {
+                    Some(capacity) => {
+                        let (vt_snd, vt_recv) = builder.bounded(short_depth);
+                        builder.add_child(Transpose::new(
+                            args.length,
+                            args.dim,
+                            capacity,
+                            v_recv,
+                            vt_snd,
+                            TransposeTimings {
+                                write_ii: args.transpose_ii,
+                                read_ii: args.transpose_ii,
+                                latency: 1,
+                            },
+                        ));
+                        vt_recv
+                    }
+                    None => v_recv,
+                }
+            })
+            .collect::<Vec<_>>();
+        (qkt_receiver, v_receivers)
     };
 
-    let output = match args.mode {
+    let pipeline = match mode {
         Implementation::Naive {
             short_depth,
             long_depth,
+            width,
             exp_ii,
             exp_latency,
             sum_ii,
             sum_latency,
+            repeat_ii,
+            repeat_latency,
+            pack_ii,
+            pack_latency,
+            unpack_ii,
+            unpack_latency,
+            pv_dataflow,
         } => {
-            if long_depth < args.length {
+            assert!(width > 0, "--width has to be at least 1");
+            if args.common.matmul_vector_width < width {
+                println!(
+                    "Warning: --matmul-vector-width is narrower than --width, the P·V matmul won't keep up with the divider."
+                );
+            }
+            if long_depth < args.length.div_ceil(width) {
                 println!(
-                    "Warning: Long Depth is shorter than the sequence length, this will deadlock."
+                    "Warning: Long Depth is shorter than a row in vectors (seq_len / width), this will deadlock."
                 );
             }
-            apps::naive::naive(
-                &mut builder,
-                qkt_receiver,
-                v_receiver,
-                config,
-                apps::naive::NaiveConfig {
+            let pv_matmul = match pv_dataflow {
+                PvDataflow::Buffered => PvMatmul::Streaming(MatmulBehavior::Buffered),
+                PvDataflow::RowWise => PvMatmul::Streaming(MatmulBehavior::RowWise),
+                PvDataflow::WeightStationary => PvMatmul::Streaming(
+                    weight_stationary
+                        .expect("The weight-stationary P·V dataflow requires --weight-buffer"),
+                ),
+                PvDataflow::Systolic => {
+                    let (dataflow, grid) =
+                        systolic.expect("The systolic P·V dataflow requires --systolic");
+                    PvMatmul::Systolic(dataflow, grid)
+                }
+            };
+            match pv_matmul {
+                PvMatmul::Streaming(behavior) => println!(
+                    "P·V matmul buffers {} elements",
+                    behavior.buffer_size(&pv_shape)
+                ),
+                PvMatmul::Systolic(dataflow, grid) => println!(
+                    "P·V matmul runs on a {}x{} {:?} systolic array",
+                    grid.rows, grid.cols, dataflow
+                ),
+            }
+            let matmul_timings = MatmulTiming {
+                dot_latency: args.common.matmul_latency,
+                dot_ii: args.common.matmul_ii,
+                reset_time: args.common.reset_time,
+                vector_width: args.common.matmul_vector_width,
+                reduction_latency: args.common.matmul_reduction_latency,
+                batch_reset_time: 0,
+            };
+            let sum_timings = ReduceTimings {
+                initiation_interval: sum_ii,
+                latency: sum_latency,
+                reset_time: args.common.reset_time,
+            };
+            let repeat_timings = RepeatTimings {
+                initiation_interval: repeat_ii,
+                latency: repeat_latency,
+            };
+            match width {
+                1 => SoftmaxPipeline::Naive(apps::naive::NaiveConfig {
                     long_chan_size: long_depth,
                     short_chan_depth: short_depth,
                     exp_timings: MapTimings {
@@ -229,18 +552,39 @@ fn main() {
                         initiation_interval: args.common.div_ii,
                         latency: args.common.div_latency,
                     },
-                    sum_timings: ReduceTimings {
-                        initiation_interval: sum_ii,
-                        latency: sum_latency,
-                        reset_time: args.common.reset_time,
+                    sum_timings,
+                    repeat_timings,
+                    matmul_timings,
+                    pv_matmul,
+                }),
+                _ => SoftmaxPipeline::VectorNaive(apps::naive::VectorNaiveConfig {
+                    width,
+                    long_chan_size: long_depth,
+                    short_chan_depth: short_depth,
+                    pack_timings: PackTimings {
+                        initiation_interval: pack_ii,
+                        latency: pack_latency,
                     },
-                    matmul_timings: MatmulTiming {
-                        dot_latency: args.common.matmul_latency,
-                        dot_ii: args.common.matmul_ii,
-                        reset_time: args.common.reset_time,
+                    unpack_timings: PackTimings {
+                        initiation_interval: unpack_ii,
+                        latency: unpack_latency,
                     },
-                },
-            )
+                    exp_timings: VectorTimings {
+                        lanes: width,
+                        initiation_interval: exp_ii,
+                        latency: exp_latency,
+                    },
+                    div_timings: VectorTimings {
+                        lanes: width,
+                        initiation_interval: args.common.div_ii,
+                        latency: args.common.div_latency,
+                    },
+                    sum_timings,
+                    repeat_timings,
+                    matmul_timings,
+                    pv_matmul,
+                }),
+            }
         }
         Implementation::Agnostic {
             channel_depth,
@@ -250,33 +594,64 @@ fn main() {
             residual_latency,
             vector_prod_ii,
             vector_prod_latency,
-        } => apps::agnostic::agnostic_attention(
+            recurrence_latency,
+            interleave,
+        } => SoftmaxPipeline::Agnostic(AgnosticConfig {
+            chan_depth: channel_depth,
+            max_config: ScanTimings {
+                initiation_interval: max_ii,
+                latency: max_latency,
+                reset_time: args.common.reset_time,
+            },
+            residual_config: ReduceTimings {
+                initiation_interval: residual_ii,
+                latency: residual_latency,
+                reset_time: args.common.reset_time,
+            },
+            prod_config: ReduceTimings {
+                initiation_interval: vector_prod_ii,
+                latency: vector_prod_latency,
+                reset_time: args.common.reset_time,
+            },
+            scale_config: FlatmapTimings {
+                initiation_interval: args.common.div_ii,
+                latency: args.common.div_latency,
+            },
+            recurrence_latency,
+            interleaved_rows: interleave,
+        }),
+    };
+    let qkt_receiver = match interleave {
+        1 => qkt_receiver,
+        _ => {
+            println!("Interleaving buffers {} elements", interleave * args.length);
+            interleave_rows(&mut builder, qkt_receiver, args.length, interleave)
+        }
+    };
+    let output = match args.lanes {
+        1 => {
+            let v_receiver = v_receivers.into_iter().next().unwrap();
+            pipeline.build(&mut builder, qkt_receiver, v_receiver, config)
+        }
+        _ => parallel_attention(
             &mut builder,
             qkt_receiver,
-            v_receiver,
+            v_receivers,
             config,
-            AgnosticConfig {
-                chan_depth: channel_depth,
-                max_config: ScanTimings {
-                    initiation_interval: max_ii,
-                    latency: max_latency,
-                    reset_time: args.common.reset_time,
-                },
-                residual_config: ReduceTimings {
-                    initiation_interval: residual_ii,
-                    latency: residual_latency,
-                    reset_time: args.common.reset_time,
-                },
-                prod_config: ReduceTimings {
-                    initiation_interval: vector_prod_ii,
-                    latency: vector_prod_latency,
-                    reset_time: args.common.reset_time,
+            ParallelConfig {
+                chan_depth: short_depth,
+                lane_depth: args.length,
+                reorder_capacity: args.reorder_buffer.unwrap_or(args.lanes * args.dim),
+                distribute_timings: DistributeTimings {
+                    initiation_interval: 1,
+                    latency: 1,
                 },
-                scale_config: FlatmapTimings {
-                    initiation_interval: args.common.div_ii,
-                    latency: args.common.div_latency,
+                reorder_timings: ReorderTimings {
+                    initiation_interval: 1,
+                    latency: 1,
                 },
             },
+            pipeline,
         ),
     };
     if args.validate {
@@ -307,5 +682,5 @@ fn main() {
         .initialize(Default::default())
         .expect("Failed to initialize and validate graph")
         .run(run_opts);
-    println!("Elapsed Cycles: {}", executed.elapsed_cycles().unwrap());
+    executed.elapsed_cycles().unwrap()
 }
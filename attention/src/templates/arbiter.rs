@@ -0,0 +1,203 @@
+use dam::{channel::PeekResult, context_tools::*};
+
+use super::{BroadcastSender, Pair};
+
+#[derive(Debug, Clone, Copy)]
+pub enum ArbiterPolicy {
+    /// Grants the next ready input after the last one granted
+    RoundRobin,
+    /// Grants the ready input with the lowest index
+    FixedPriority,
+    /// Grants the ready input whose element arrived first, ties going to the lowest index
+    OldestFirst,
+}
+
+pub struct ArbiterTimings {
+    /// Cycles between grants
+    pub initiation_interval: u64,
+    /// Cycles to make a decision and forward the granted element
+    pub latency: u64,
+}
+
+/// Merges several streams into one, tagging every element with the index of its input.
+/// Only elements that have arrived by the current cycle take part in arbitration.
+#[context_macro]
+pub struct Arbiter<T: DAMType> {
+    inputs: Vec<Receiver<T>>,
+    output: BroadcastSender<Pair<usize, T>>,
+    policy: ArbiterPolicy,
+    timings: ArbiterTimings,
+}
+
+impl<T: DAMType> Arbiter<T>
+where
+    Self: Context,
+{
+    pub fn new(
+        inputs: Vec<Receiver<T>>,
+        output: BroadcastSender<Pair<usize, T>>,
+        policy: ArbiterPolicy,
+        timings: ArbiterTimings,
+    ) -> Self {
+        assert!(!inputs.is_empty());
+        let s = Self {
+            inputs,
+            output,
+            policy,
+            timings,
+            context_info: Default::default(),
+        };
+        s.inputs.iter().for_each(|chn| chn.attach_receiver(&s));
+        s.output.attach_sender(&s);
+        s
+    }
+
+    /// Picks an input among those with an element ready, given as (input, arrival time).
+    fn grant(&self, ready: &[(usize, u64)], last: usize) -> usize {
+        match self.policy {
+            ArbiterPolicy::RoundRobin => {
+                let n = self.inputs.len();
+                ready
+                    .iter()
+                    .min_by_key(|(input, _)| (input + n - last - 1) % n)
+                    .unwrap()
+                    .0
+            }
+            ArbiterPolicy::FixedPriority => ready[0].0,
+            ArbiterPolicy::OldestFirst => ready.iter().min_by_key(|(_, time)| *time).unwrap().0,
+        }
+    }
+}
+
+impl<T: DAMType> Context for Arbiter<T> {
+    fn run(&mut self) {
+        let mut last = self.inputs.len() - 1;
+        loop {
+            let now = self.time.tick().time();
+            let mut ready = vec![];
+            // Nothing can arrive before the next element or before every sender reaches this.
+            let mut next_arrival = None;
+            for (input, chn) in self.inputs.iter().enumerate() {
+                let time = match chn.peek() {
+                    PeekResult::Something(ChannelElement { time, data: _ })
+                        if time.time() <= now =>
+                    {
+                        ready.push((input, time.time()));
+                        continue;
+                    }
+                    PeekResult::Something(ChannelElement { time, data: _ }) => time.time(),
+                    PeekResult::Nothing(time) => time.time(),
+                    PeekResult::Closed => continue,
+                };
+                if next_arrival.is_none_or(|(t, _)| time < t) {
+                    next_arrival = Some((time, input));
+                }
+            }
+            if ready.is_empty() {
+                match next_arrival {
+                    None => return,
+                    Some((time, _)) if time > now => self.time.incr_cycles(time - now),
+                    // That sender hasn't caught up yet, so wait on it.
+                    Some((_, input)) => {
+                        let _ = self.inputs[input].peek_next(&self.time);
+                    }
+                }
+                continue;
+            }
+
+            let input = self.grant(&ready, last);
+            last = input;
+            let ChannelElement { time: _, data } = self.inputs[input].dequeue(&self.time).unwrap();
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data: Pair(input, data),
+                    },
+                )
+                .unwrap_or_else(|_| panic!("Premature End of Sender on Arbiter {:?}", self.id));
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::{BroadcastSender, Map, MapTimings, Pair};
+
+    use super::{Arbiter, ArbiterPolicy, ArbiterTimings};
+
+    #[test]
+    fn arbiter_test() {
+        const INPUTS: usize = 3;
+        const LEN: u64 = 32;
+        for policy in [
+            ArbiterPolicy::RoundRobin,
+            ArbiterPolicy::FixedPriority,
+            ArbiterPolicy::OldestFirst,
+        ] {
+            let mut builder = ProgramBuilder::default();
+            let mut inputs = vec![];
+            for i in 0..INPUTS {
+                let (snd, rcv) = builder.bounded(4);
+                builder.add_child(GeneratorContext::new(
+                    move || (0..LEN).map(move |x| x * INPUTS as u64 + i as u64),
+                    snd,
+                ));
+                inputs.push(rcv);
+            }
+            let (merged_snd, merged_rcv) = builder.bounded(4);
+            builder.add_child(Arbiter::new(
+                inputs,
+                BroadcastSender {
+                    targets: vec![merged_snd],
+                },
+                policy,
+                ArbiterTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+            ));
+            // The interleaving depends on the policy, but every element has to come out exactly
+            // once, tagged with its source, and each source has to stay in order.
+            let (check_snd, check_rcv) = builder.bounded(4);
+            let next = Mutex::new([0u64; INPUTS]);
+            builder.add_child(Map::new(
+                vec![merged_rcv],
+                BroadcastSender {
+                    targets: vec![check_snd],
+                },
+                move |merged: &[Pair<usize, u64>]| {
+                    let Pair(source, value) = merged[0];
+                    assert_eq!(value % INPUTS as u64, source as u64);
+                    let mut next = next.lock().unwrap();
+                    assert_eq!(value / INPUTS as u64, next[source]);
+                    next[source] += 1;
+                    1u64
+                },
+                MapTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+            ));
+            builder.add_child(CheckerContext::new(
+                || std::iter::repeat_n(1u64, INPUTS * LEN as usize),
+                check_rcv,
+            ));
+            let elapsed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default())
+                .elapsed_cycles();
+            dbg!(policy, elapsed);
+        }
+    }
+}
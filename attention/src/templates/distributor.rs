@@ -0,0 +1,208 @@
+use dam::context_tools::*;
+
+use super::Pair;
+
+#[derive(Debug, Clone, Copy)]
+pub enum DistributePolicy {
+    /// Element i goes to output i % N
+    RoundRobin,
+    /// Consecutive blocks of this many elements (e.g. whole rows) go to consecutive outputs
+    Blocks(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DistributeTimings {
+    /// Cycles between elements
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+/// Splits one stream across several outputs.
+#[context_macro]
+pub struct Distributor<T: DAMType> {
+    input: Receiver<T>,
+    outputs: Vec<Sender<T>>,
+    policy: DistributePolicy,
+    timings: DistributeTimings,
+}
+
+impl<T: DAMType> Distributor<T>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<T>,
+        outputs: Vec<Sender<T>>,
+        policy: DistributePolicy,
+        timings: DistributeTimings,
+    ) -> Self {
+        assert!(!outputs.is_empty());
+        if let DistributePolicy::Blocks(size) = policy {
+            assert!(size > 0);
+        }
+        let s = Self {
+            input,
+            outputs,
+            policy,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.outputs.iter().for_each(|chn| chn.attach_sender(&s));
+        s
+    }
+}
+
+impl<T: DAMType> Context for Distributor<T> {
+    fn run(&mut self) {
+        for index in 0.. {
+            let data = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => data,
+                Err(_) => return,
+            };
+            let output = match self.policy {
+                DistributePolicy::RoundRobin => index % self.outputs.len(),
+                DistributePolicy::Blocks(size) => (index / size) % self.outputs.len(),
+            };
+            self.outputs[output]
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data,
+                    },
+                )
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "Premature End of Sender {output} on Distributor {:?}",
+                        self.id
+                    )
+                });
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+/// Routes every element to the output given by its tag, e.g. to undo an [super::Arbiter].
+#[context_macro]
+pub struct Demux<T: DAMType> {
+    input: Receiver<Pair<usize, T>>,
+    outputs: Vec<Sender<T>>,
+    timings: DistributeTimings,
+}
+
+impl<T: DAMType> Demux<T>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<Pair<usize, T>>,
+        outputs: Vec<Sender<T>>,
+        timings: DistributeTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            outputs,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.outputs.iter().for_each(|chn| chn.attach_sender(&s));
+        s
+    }
+}
+
+impl<T: DAMType> Context for Demux<T> {
+    fn run(&mut self) {
+        loop {
+            let Pair(tag, data) = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => data,
+                Err(_) => return,
+            };
+            let output = self.outputs.get(tag).unwrap_or_else(|| {
+                panic!(
+                    "Tag {tag} on Demux {:?} with {} outputs",
+                    self.id,
+                    self.outputs.len()
+                )
+            });
+            output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data,
+                    },
+                )
+                .unwrap_or_else(|_| panic!("Premature End of Sender {tag} on Demux {:?}", self.id));
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::{Arbiter, ArbiterPolicy, ArbiterTimings, BroadcastSender};
+
+    use super::{Demux, DistributePolicy, DistributeTimings, Distributor};
+
+    const TIMINGS: DistributeTimings = DistributeTimings {
+        initiation_interval: 1,
+        latency: 1,
+    };
+
+    #[test]
+    fn distributor_test() {
+        const LANES: usize = 3;
+        const LEN: usize = 60;
+        for (policy, block) in [
+            (DistributePolicy::RoundRobin, 1),
+            (DistributePolicy::Blocks(4), 4),
+        ] {
+            let mut builder = ProgramBuilder::default();
+            let (in_snd, in_rcv) = builder.bounded(4);
+            builder.add_child(GeneratorContext::new(|| 0..LEN, in_snd));
+            let mut lanes = vec![];
+            let mut lane_receivers = vec![];
+            for _ in 0..LANES {
+                let (snd, rcv) = builder.bounded(4);
+                lanes.push(snd);
+                lane_receivers.push(rcv);
+            }
+            builder.add_child(Distributor::new(in_rcv, lanes, policy, TIMINGS));
+
+            // Merging the lanes and splitting them again by tag gives back the same lanes.
+            let (merged_snd, merged_rcv) = builder.bounded(4);
+            builder.add_child(Arbiter::new(
+                lane_receivers,
+                BroadcastSender {
+                    targets: vec![merged_snd],
+                },
+                ArbiterPolicy::RoundRobin,
+                ArbiterTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+            ));
+            let mut outputs = vec![];
+            for lane in 0..LANES {
+                let (snd, rcv) = builder.bounded(4);
+                outputs.push(snd);
+                builder.add_child(CheckerContext::new(
+                    move || (0..LEN).filter(move |x| (x / block) % LANES == lane),
+                    rcv,
+                ));
+            }
+            builder.add_child(Demux::new(merged_rcv, outputs, TIMINGS));
+            builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use dam::context_tools::*;
+
+use super::BroadcastSender;
+
+/// A stream element along with whether it should be used, so that masked or padded elements
+/// keep their place in the stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Predicated<T> {
+    pub value: T,
+    pub valid: bool,
+}
+
+impl<T: DAMType> DAMType for Predicated<T> {
+    fn dam_size(&self) -> usize {
+        self.value.dam_size() + 1
+    }
+}
+
+pub struct FilterTimings {
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+/// Drops the elements that don't satisfy the predicate.
+#[context_macro]
+pub struct Filter<T: DAMType, PredF> {
+    input: Receiver<T>,
+    output: BroadcastSender<T>,
+    predicate: PredF,
+    timings: FilterTimings,
+}
+
+impl<T: DAMType, PredF> Filter<T, PredF>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<T>,
+        output: BroadcastSender<T>,
+        predicate: PredF,
+        timings: FilterTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            output,
+            predicate,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType, PredF> Context for Filter<T, PredF>
+where
+    PredF: Fn(&T) -> bool + Sync + Send,
+{
+    fn run(&mut self) {
+        loop {
+            let data = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => data,
+                Err(_) => return,
+            };
+            if (self.predicate)(&data) {
+                self.output
+                    .enqueue(
+                        &self.time,
+                        ChannelElement {
+                            time: self.time.tick() + self.timings.latency,
+                            data,
+                        },
+                    )
+                    .unwrap_or_else(|_| panic!("Premature End of Sender on Filter {:?}", self.id));
+            }
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+/// Like [Filter], but marks the elements that don't satisfy the predicate as invalid instead of
+/// dropping them.
+#[context_macro]
+pub struct Predicate<T: DAMType, PredF> {
+    input: Receiver<T>,
+    output: BroadcastSender<Predicated<T>>,
+    predicate: PredF,
+    timings: FilterTimings,
+}
+
+impl<T: DAMType, PredF> Predicate<T, PredF>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<T>,
+        output: BroadcastSender<Predicated<T>>,
+        predicate: PredF,
+        timings: FilterTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            output,
+            predicate,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType, PredF> Context for Predicate<T, PredF>
+where
+    PredF: Fn(&T) -> bool + Sync + Send,
+{
+    fn run(&mut self) {
+        loop {
+            let value = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => data,
+                Err(_) => return,
+            };
+            let valid = (self.predicate)(&value);
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data: Predicated { value, valid },
+                    },
+                )
+                .unwrap_or_else(|_| panic!("Premature End of Sender on Predicate {:?}", self.id));
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::{BroadcastSender, Reduce, ReduceTimings, Scan, ScanTimings};
+
+    use super::{Filter, FilterTimings, Predicate, Predicated};
+
+    #[test]
+    fn filter_test() {
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(16);
+        let (out_snd, out_rcv) = builder.bounded(16);
+        builder.add_child(GeneratorContext::new(|| 0..64u64, in_snd));
+        builder.add_child(Filter::new(
+            in_rcv,
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            |x: &u64| x.is_multiple_of(3),
+            FilterTimings {
+                initiation_interval: 1,
+                latency: 1,
+            },
+        ));
+        builder.add_child(CheckerContext::new(
+            || (0..64u64).filter(|x| x.is_multiple_of(3)),
+            out_rcv,
+        ));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+
+    #[test]
+    fn predicated_reduce_test() {
+        // Sum rows of 8, skipping the odd elements. The last row has no valid elements at all.
+        let values: Vec<u64> = (0..24).chain((0..8).map(|x| 2 * x + 1)).collect();
+        let gold: Vec<u64> = values
+            .chunks(8)
+            .map(|row| row.iter().filter(|x| x.is_multiple_of(2)).sum())
+            .collect();
+
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(16);
+        let (pred_snd, pred_rcv) = builder.bounded(16);
+        let (out_snd, out_rcv) = builder.bounded(16);
+        builder.add_child(GeneratorContext::new(|| values.into_iter(), in_snd));
+        builder.add_child(Predicate::new(
+            in_rcv,
+            BroadcastSender {
+                targets: vec![pred_snd],
+            },
+            |x: &u64| x.is_multiple_of(2),
+            FilterTimings {
+                initiation_interval: 1,
+                latency: 1,
+            },
+        ));
+        builder.add_child(
+            Reduce::new(
+                8,
+                pred_rcv,
+                out_snd,
+                |new: Predicated<u64>, old| match old {
+                    Some(old_val) => new.value + old_val,
+                    None => new.value,
+                },
+                ReduceTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+            )
+            .skip_invalid(),
+        );
+        builder.add_child(CheckerContext::new(|| gold.into_iter(), out_rcv));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+
+    #[test]
+    fn predicated_scan_test() {
+        // Running sums over rows of 8 that skip the odd elements. Every row starts with an odd
+        // element, and the last row has no valid elements at all.
+        let values: Vec<u64> = (1..25).chain((0..8).map(|x| 2 * x + 1)).collect();
+        let gold: Vec<u64> = values
+            .chunks(8)
+            .flat_map(|row| {
+                row.iter().scan(0, |sum, x| {
+                    if x.is_multiple_of(2) {
+                        *sum += x;
+                    }
+                    Some(*sum)
+                })
+            })
+            .collect();
+
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(16);
+        let (pred_snd, pred_rcv) = builder.bounded(16);
+        let (out_snd, out_rcv) = builder.bounded(16);
+        builder.add_child(GeneratorContext::new(|| values.into_iter(), in_snd));
+        builder.add_child(Predicate::new(
+            in_rcv,
+            BroadcastSender {
+                targets: vec![pred_snd],
+            },
+            |x: &u64| x.is_multiple_of(2),
+            FilterTimings {
+                initiation_interval: 1,
+                latency: 1,
+            },
+        ));
+        builder.add_child(
+            Scan::new(
+                8,
+                pred_rcv,
+                BroadcastSender {
+                    targets: vec![out_snd],
+                },
+                |new: Predicated<u64>, old| match old {
+                    Some(old_val) => new.value + old_val,
+                    None => new.value,
+                },
+                ScanTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+            )
+            .skip_invalid(),
+        );
+        builder.add_child(CheckerContext::new(|| gold.into_iter(), out_rcv));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+}
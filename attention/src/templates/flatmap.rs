@@ -2,6 +2,7 @@ use dam::{context_tools::*, structures::SyncSendMarker};
 
 use super::BroadcastSender;
 
+#[derive(Debug, Clone, Copy)]
 pub struct FlatmapTimings {
     pub initiation_interval: u64,
     pub latency: u64,
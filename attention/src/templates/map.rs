@@ -1,7 +1,8 @@
 use dam::context_tools::*;
 
-use super::BroadcastSender;
+use super::{BroadcastSender, ZipInputs};
 
+#[derive(Debug, Clone, Copy)]
 pub struct MapTimings {
     pub initiation_interval: u64,
     pub latency: u64,
@@ -72,6 +73,60 @@ where
     }
 }
 
+/// A Map whose inputs can carry different types, e.g. a tuple of receivers.
+/// The function takes one element of every input, as a tuple.
+#[context_macro]
+pub struct MapN<I: ZipInputs, OutT: DAMType, MapF> {
+    input: I,
+    output: BroadcastSender<OutT>,
+    mapf: MapF,
+    timings: MapTimings,
+}
+
+impl<I: ZipInputs, OutT: DAMType, MapF> MapN<I, OutT, MapF>
+where
+    Self: Context,
+{
+    pub fn new(input: I, output: BroadcastSender<OutT>, mapf: MapF, timings: MapTimings) -> Self {
+        let s = Self {
+            input,
+            output,
+            mapf,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receivers(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<I: ZipInputs, OutT: DAMType, MapF> Context for MapN<I, OutT, MapF>
+where
+    MapF: Fn(I::Item) -> OutT + Sync + Send,
+{
+    fn run(&mut self) {
+        loop {
+            // Block on all of the inputs
+            let data = match self.input.dequeue_all(&self.time) {
+                Ok(data) => data,
+                Err(_) => return,
+            };
+            let output = (self.mapf)(data);
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data: output,
+                    },
+                )
+                .unwrap();
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use dam::{
@@ -81,7 +136,7 @@ mod tests {
 
     use crate::templates::BroadcastSender;
 
-    use super::{Map, MapTimings};
+    use super::{Map, MapN, MapTimings};
 
     #[test]
     fn test_map() {
@@ -108,4 +163,38 @@ mod tests {
             .elapsed_cycles();
         dbg!(elapsed);
     }
+
+    #[test]
+    fn test_map_n() {
+        let mut builder = ProgramBuilder::default();
+        let (scale_snd, scale_rcv) = builder.bounded(16);
+        let (offset_snd, offset_rcv) = builder.bounded(16);
+        let (out_snd, out_rcv) = builder.bounded(16);
+        builder.add_child(GeneratorContext::new(
+            || (0..16).map(|x| x as f32),
+            scale_snd,
+        ));
+        builder.add_child(GeneratorContext::new(|| 0..16usize, offset_snd));
+        builder.add_child(CheckerContext::new(
+            || (0..16).map(|x| (x * x + x) as f32),
+            out_rcv,
+        ));
+        builder.add_child(MapN::new(
+            (scale_rcv, offset_rcv),
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            |(scale, offset): (f32, usize)| scale * offset as f32 + offset as f32,
+            MapTimings {
+                initiation_interval: 1,
+                latency: 5,
+            },
+        ));
+        let elapsed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default())
+            .elapsed_cycles();
+        dbg!(elapsed);
+    }
 }
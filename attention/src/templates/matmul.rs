@@ -5,6 +5,42 @@ pub struct MatmulTiming {
     pub dot_latency: u64,
     pub dot_ii: u64,
     pub reset_time: u64,
+    /// Number of elements consumed per dot_ii along the vectorized dimension
+    /// (K for the inner-product behaviors, N for the outer-product and row-wise ones)
+    pub vector_width: usize,
+    /// Latency of the adder tree that reduces the vector_width products
+    pub reduction_latency: u64,
+    /// Paid once at the start of every matmul in a batch
+    pub batch_reset_time: u64,
+}
+
+impl MatmulTiming {
+    /// Whether the vector unit issues after consuming element `i` of a dimension of length `len`.
+    /// The last chunk may be narrower than vector_width.
+    fn issues_after(&self, i: usize, len: usize) -> bool {
+        i % self.vector_width == self.vector_width - 1 || i + 1 == len
+    }
+
+    fn output_latency(&self) -> u64 {
+        self.dot_latency + self.reduction_latency
+    }
+}
+
+/// Timing of an elementwise unit fused onto the matmul accumulator output
+#[derive(Debug, Copy, Clone)]
+pub struct EpilogueTiming {
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+/// Which operand, if any, is shared by every matmul in a batch.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BatchBroadcast {
+    None,
+    /// e.g. a block of queries shared by several heads
+    Left,
+    /// e.g. shared weights, or the K/V of a group of query heads (GQA)
+    Right,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -12,20 +48,130 @@ pub struct ShapeInfo {
     pub m: usize,
     pub n: usize,
     pub k: usize,
+    /// Number of matmuls in a batch
+    pub batch: usize,
+    /// A broadcast operand is streamed in once per batch instead of once per matmul.
+    pub broadcast: BatchBroadcast,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum MatmulBehavior {
     Buffered,
     Repeated,
+    OuterProduct,
+    RowWise,
+    /// B^T is loaded into a buffer of `capacity` elements (`load_ii` cycles per element) and
+    /// replayed for every row of A. If it doesn't fit, the buffer holds as many columns of B as
+    /// it can, and they are reloaded for every row of A, a buffer's worth at a time.
+    WeightStationary {
+        capacity: usize,
+        load_ii: u64,
+    },
+}
+
+impl MatmulBehavior {
+    /// Number of elements held on-chip by the matmul for the given shape.
+    pub fn buffer_size(&self, shape: &ShapeInfo) -> usize {
+        let operands = match self {
+            MatmulBehavior::Buffered => shape.k,
+            MatmulBehavior::Repeated => 0,
+            // A column of A, a row of B and the full partial output.
+            MatmulBehavior::OuterProduct => shape.m + shape.n + shape.m * shape.n,
+            // One element of A and one row of partial outputs; B streams straight through.
+            MatmulBehavior::RowWise => 1 + shape.n,
+            // A row of A alongside as much of the right operand as fits.
+            MatmulBehavior::WeightStationary { capacity, .. } => {
+                shape.k + (*capacity).min(shape.k * shape.n)
+            }
+        };
+        let (left_replay, right_replay) = self.replay_sizes(shape);
+        operands + left_replay.unwrap_or(0) + right_replay.unwrap_or(0)
+    }
+
+    /// Number of times the right operand is streamed in per matmul.
+    pub fn right_passes(&self, shape: &ShapeInfo) -> usize {
+        match self {
+            MatmulBehavior::OuterProduct => 1,
+            MatmulBehavior::WeightStationary { capacity, .. } if *capacity >= shape.k * shape.n => {
+                1
+            }
+            _ => shape.m,
+        }
+    }
+
+    /// Elements of a broadcast operand kept on-chip to replay it for the rest of the batch,
+    /// as (left, right).
+    fn replay_sizes(&self, shape: &ShapeInfo) -> (Option<usize>, Option<usize>) {
+        match (shape.broadcast, self) {
+            (BatchBroadcast::None, _) => (None, None),
+            // A is streamed once per column of B
+            (BatchBroadcast::Left, MatmulBehavior::Repeated) => {
+                (Some(shape.m * shape.n * shape.k), None)
+            }
+            (BatchBroadcast::Left, _) => (Some(shape.m * shape.k), None),
+            // The weight buffer already holds all of B
+            (BatchBroadcast::Right, MatmulBehavior::WeightStationary { .. }) => (None, None),
+            // Every ordering of B repeats after K x N elements
+            (BatchBroadcast::Right, _) => (None, Some(shape.k * shape.n)),
+        }
+    }
+}
+
+/// An operand of a batched matmul. When broadcast, its stream only covers the first matmul of
+/// a batch, and its first `period` elements are replayed for the remaining ones.
+struct BatchOperand<T> {
+    period: Option<usize>,
+    replay: Vec<T>,
+    cursor: usize,
+}
+
+impl<T: Clone> BatchOperand<T> {
+    fn new(period: Option<usize>) -> Self {
+        Self {
+            period,
+            replay: Vec::with_capacity(period.unwrap_or(0)),
+            cursor: 0,
+        }
+    }
+
+    fn start_batch(&mut self, batch: usize) {
+        self.cursor = 0;
+        if batch == 0 {
+            self.replay.clear();
+        }
+    }
+
+    fn replaying(&self, batch: usize) -> bool {
+        self.period.is_some() && batch > 0
+    }
+
+    fn record(&mut self, data: &T) {
+        if let Some(period) = self.period {
+            if self.replay.len() < period {
+                self.replay.push(data.clone());
+            }
+        }
+    }
+
+    fn next_replayed(&mut self) -> T {
+        let data = self.replay[self.cursor % self.replay.len()].clone();
+        self.cursor += 1;
+        data
+    }
 }
 
 /// Computes A: [M, K] x B[K, N] = C [M, N]
 /// Options:
 /// 1. The K dimension of A is buffered, so it reads it once.
 /// 2. The K dimension of A is repeated, so it reads it once per iteration (repeated M times)
+/// 3. Outer product: K is outermost, every column of A and row of B updates a full M x N partial output.
+/// 4. Row-wise (Gustavson): each element A[m, k] scales row k of B into a partial output row m.
+/// 5. Weight stationary: like 1., but B is also buffered, so it is read once instead of once per row of A.
+/// The vectorized dimension is processed vector_width elements at a time, with the last chunk possibly partial.
+/// Every batch runs shape.batch matmuls, and an optional bias row is added to every row of C.
+/// An optional epilogue is applied to every accumulator before it is sent out.
 #[context_macro]
-pub struct Matmul<InputT, OutputT, MacT>
+pub struct Matmul<InputT, OutputT, MacT, EpilogueT = fn(OutputT) -> OutputT>
 where
     InputT: DAMType,
     OutputT: DAMType,
@@ -37,6 +183,9 @@ where
     right: Receiver<InputT>,
     output: Sender<OutputT>,
     mac: MacT,
+    bias: Option<Receiver<OutputT>>,
+    epilogue: EpilogueT,
+    epilogue_timing: EpilogueTiming,
 }
 
 impl<InputT, OutputT, MacT> Matmul<InputT, OutputT, MacT>
@@ -54,6 +203,21 @@ where
         output: Sender<OutputT>,
         mac: MacT,
     ) -> Self {
+        assert!(timing.vector_width > 0);
+        assert!(shape.batch > 0);
+        if let MatmulBehavior::WeightStationary { capacity, .. } = behavior {
+            assert!(
+                capacity >= shape.k,
+                "Weight buffer of {capacity} elements can't hold a column of {} elements",
+                shape.k
+            );
+            assert!(
+                shape.broadcast != BatchBroadcast::Right || capacity >= shape.k * shape.n,
+                "A broadcast {}x{} right operand has to fit in the weight buffer of {capacity}",
+                shape.k,
+                shape.n
+            );
+        }
         let output = Self {
             timing,
             behavior,
@@ -62,6 +226,12 @@ where
             right,
             output,
             mac,
+            bias: None,
+            epilogue: std::convert::identity,
+            epilogue_timing: EpilogueTiming {
+                initiation_interval: 0,
+                latency: 0,
+            },
             context_info: Default::default(),
         };
         output.left.attach_receiver(&output);
@@ -70,126 +240,432 @@ where
         output
     }
 
+    /// Fuses an elementwise function onto the output, e.g. the exp following QK^T.
+    pub fn with_epilogue<EpilogueT>(
+        self,
+        epilogue: EpilogueT,
+        epilogue_timing: EpilogueTiming,
+    ) -> Matmul<InputT, OutputT, MacT, EpilogueT>
+    where
+        EpilogueT: Fn(OutputT) -> OutputT + Sync + Send,
+    {
+        Matmul {
+            timing: self.timing,
+            behavior: self.behavior,
+            shape: self.shape,
+            left: self.left,
+            right: self.right,
+            output: self.output,
+            mac: self.mac,
+            bias: self.bias,
+            epilogue,
+            epilogue_timing,
+            // Keep the identity the channels were attached to.
+            context_info: self.context_info,
+        }
+    }
+}
+
+impl<InputT, OutputT, MacT, EpilogueT> Matmul<InputT, OutputT, MacT, EpilogueT>
+where
+    InputT: DAMType,
+    OutputT: DAMType + num::Float,
+    MacT: Fn(InputT, InputT, OutputT) -> OutputT + Sync + Send,
+    EpilogueT: Fn(OutputT) -> OutputT + Sync + Send,
+{
+    /// Adds a bias to every row of C. The bias stream holds N elements per matmul.
+    pub fn with_bias(mut self, bias: Receiver<OutputT>) -> Self {
+        bias.attach_receiver(&self);
+        self.bias = Some(bias);
+        self
+    }
+
+    fn operands(&self) -> (BatchOperand<InputT>, BatchOperand<InputT>) {
+        let (left, right) = self.behavior.replay_sizes(&self.shape);
+        (BatchOperand::new(left), BatchOperand::new(right))
+    }
+
+    /// Prepares the operands for the next matmul of a batch and reads its bias.
+    /// Returns None if the bias stream ended before the batch started.
+    fn start_batch(
+        &self,
+        batch: usize,
+        left: &mut BatchOperand<InputT>,
+        right: &mut BatchOperand<InputT>,
+    ) -> Option<Vec<OutputT>> {
+        left.start_batch(batch);
+        right.start_batch(batch);
+        let bias = match &self.bias {
+            None => vec![OutputT::zero(); self.shape.n],
+            Some(bias) => {
+                let mut values = Vec::with_capacity(self.shape.n);
+                for n in 0..self.shape.n {
+                    match bias.dequeue(&self.time) {
+                        Ok(ChannelElement { time: _, data }) => values.push(data),
+                        Err(_) if batch == 0 && n == 0 => return None,
+                        Err(_) => {
+                            panic!("Unexpected termination of bias stream in matmul ID: {:?} at time {:?} on iteration {batch}, {n}", self.id, self.time.tick());
+                        }
+                    }
+                }
+                values
+            }
+        };
+        self.time.incr_cycles(self.timing.batch_reset_time);
+        Some(bias)
+    }
+
+    /// Next element of an operand, or None if its stream has ended.
+    fn read(
+        &self,
+        receiver: &Receiver<InputT>,
+        operand: &mut BatchOperand<InputT>,
+        batch: usize,
+    ) -> Option<InputT> {
+        if operand.replaying(batch) {
+            return Some(operand.next_replayed());
+        }
+        let ChannelElement { time: _, data } = receiver.dequeue(&self.time).ok()?;
+        operand.record(&data);
+        Some(data)
+    }
+
+    /// Waits for the next element of an operand. Returns false if its stream has ended.
+    fn wait(
+        &self,
+        receiver: &Receiver<InputT>,
+        operand: &BatchOperand<InputT>,
+        batch: usize,
+    ) -> bool {
+        operand.replaying(batch) || receiver.peek_next(&self.time).is_ok()
+    }
+
+    /// Sends an accumulator through the epilogue. The epilogue accepts a new accumulator every
+    /// initiation_interval cycles, stalling the matmul if it can't keep up.
+    fn emit(&self, accum: OutputT, epilogue_free: &mut u64) {
+        let now = self.time.tick().time();
+        let stall = epilogue_free.saturating_sub(now);
+        self.time.incr_cycles(stall);
+        *epilogue_free = now + stall + self.epilogue_timing.initiation_interval;
+        self.output
+            .enqueue(
+                &self.time,
+                ChannelElement {
+                    time: self.time.tick()
+                        + self.timing.output_latency()
+                        + self.epilogue_timing.latency,
+                    data: (self.epilogue)(accum),
+                },
+            )
+            .unwrap_or_else(|_| {
+                panic!(
+                    "Unexpected termination of output channel on Matmul {:?}",
+                    self.id
+                )
+            });
+    }
+
     fn buffered_matmul(&self) {
+        let mut epilogue_free = 0;
+        let (mut left_op, mut right_op) = self.operands();
         let mut left_buffer = Vec::with_capacity(self.shape.k);
         loop {
-            // Loop over M
-            for m in 0..self.shape.m {
-                for n in 0..self.shape.n {
-                    let should_populate_buffer = n == 0;
-                    let mut accum = OutputT::zero();
-                    for k in 0..self.shape.k {
-                        // Align the two timings
-                        let right_peek = self.right.peek_next(&self.time);
-                        if right_peek.is_err() {
-                            if m == 0 && n == 0 && k == 0 {
-                                return;
+            for batch in 0..self.shape.batch {
+                let bias = match self.start_batch(batch, &mut left_op, &mut right_op) {
+                    Some(bias) => bias,
+                    None => return,
+                };
+                // Loop over M
+                for m in 0..self.shape.m {
+                    for (n, bias) in bias.iter().enumerate() {
+                        let should_populate_buffer = n == 0;
+                        let mut accum = OutputT::zero();
+                        for k in 0..self.shape.k {
+                            // Align the two timings
+                            if !self.wait(&self.right, &right_op, batch) {
+                                if batch == 0 && m == 0 && n == 0 && k == 0 {
+                                    return;
+                                }
+                                panic!("Unexpected termination of right stream in matmul ID: {:?} at time {:?} on iteration {batch}, {m}, {n}, {k}", self.id, self.time.tick());
                             }
-                            panic!("Unexpected termination of right stream in matmul ID: {:?} at time {:?} on iteration {m}, {n}, {k}", self.id, self.time.tick());
-                        }
-                        if should_populate_buffer {
-                            match self.left.dequeue(&self.time) {
-                                Ok(ChannelElement { time: _, data }) => left_buffer.push(data),
-                                Err(_) if m == 0 && n == 0 && k == 0 => return,
-                                Err(_) => {
-                                    panic!("Unexpected termination of left stream in matmul ID: {:?} at time {:?} on iteration {m}, {n}, {k}", self.id, self.time.tick());
+                            if should_populate_buffer {
+                                match self.read(&self.left, &mut left_op, batch) {
+                                    Some(data) => left_buffer.push(data),
+                                    None if batch == 0 && m == 0 && n == 0 && k == 0 => return,
+                                    None => {
+                                        panic!("Unexpected termination of left stream in matmul ID: {:?} at time {:?} on iteration {batch}, {m}, {n}, {k}", self.id, self.time.tick());
+                                    }
                                 }
                             }
+                            let right_data = self.read(&self.right, &mut right_op, batch).unwrap();
+                            let left_data = left_buffer[k].clone();
+                            accum = (self.mac)(left_data, right_data, accum);
+                            if self.timing.issues_after(k, self.shape.k) {
+                                self.time.incr_cycles(self.timing.dot_ii);
+                            }
                         }
-                        let ChannelElement {
-                            time: _,
-                            data: right_data,
-                        } = self.right.dequeue(&self.time).unwrap();
-                        let left_data = left_buffer[k].clone();
-                        accum = (self.mac)(left_data, right_data, accum);
-                        self.time.incr_cycles(self.timing.dot_ii);
+                        // After K values, we spit out the accum.
+                        self.emit(accum + *bias, &mut epilogue_free);
                     }
-                    // After K values, we spit out the accum.
-                    self.output
-                        .enqueue(
-                            &self.time,
-                            ChannelElement {
-                                time: self.time.tick() + self.timing.dot_latency,
-                                data: accum,
-                            },
-                        )
-                        .unwrap_or_else(|_| {
-                            panic!(
-                                "Unexpected termination of output channel on Matmul {:?}",
-                                self.id
-                            )
-                        });
+                    // Reset buffer after N elements as we prepare to read the next inputs.
+                    left_buffer.clear();
                 }
-                // Reset buffer after N elements as we prepare to read the next inputs.
-                left_buffer.clear();
             }
         }
     }
+
     fn repeated_matmul(&self) {
+        let mut epilogue_free = 0;
+        let (mut left_op, mut right_op) = self.operands();
         // For processing multiple batches
         loop {
-            // Looping over M
-            for m in 0..self.shape.m {
+            for batch in 0..self.shape.batch {
+                let bias = match self.start_batch(batch, &mut left_op, &mut right_op) {
+                    Some(bias) => bias,
+                    None => return,
+                };
+                // Looping over M
+                for m in 0..self.shape.m {
+                    self.time.incr_cycles(self.timing.reset_time);
+                    // Looping over N
+                    for (n, bias) in bias.iter().enumerate() {
+                        let mut accum = OutputT::zero();
+                        // Looping over K (common dim)
+                        for k in 0..self.shape.k {
+                            let _ = self.wait(&self.left, &left_op, batch);
+                            let _ = self.wait(&self.right, &right_op, batch);
+                            match (
+                                self.read(&self.left, &mut left_op, batch),
+                                self.read(&self.right, &mut right_op, batch),
+                            ) {
+                                (Some(a), Some(b)) => {
+                                    // Perform a MAC
+                                    accum = (self.mac)(a, b, accum);
+                                }
+                                (_, None) | (None, _)
+                                    if (batch == 0 && m == 0 && n == 0 && k == 0) =>
+                                {
+                                    // Finished all of our iterations
+                                    return;
+                                }
+                                _ => {
+                                    panic!("Unexpected termination of streams in matmul ID: {:?} at time {:?} on iteration {batch}, {m}, {n}, {k}", self.id, self.time.tick());
+                                }
+                            }
+
+                            if self.timing.issues_after(k, self.shape.k) {
+                                self.time.incr_cycles(self.timing.dot_ii);
+                            }
+                        }
+                        self.emit(accum + *bias, &mut epilogue_free);
+                    }
+                }
+            }
+        }
+    }
+
+    fn outer_product_matmul(&self) {
+        let mut epilogue_free = 0;
+        let (mut left_op, mut right_op) = self.operands();
+        let mut a_column = Vec::with_capacity(self.shape.m);
+        let mut b_row = Vec::with_capacity(self.shape.n);
+        loop {
+            for batch in 0..self.shape.batch {
+                let bias = match self.start_batch(batch, &mut left_op, &mut right_op) {
+                    Some(bias) => bias,
+                    None => return,
+                };
                 self.time.incr_cycles(self.timing.reset_time);
-                // Looping over N
-                for n in 0..self.shape.n {
-                    let mut accum = OutputT::zero();
+                let mut partials = vec![OutputT::zero(); self.shape.m * self.shape.n];
+                // Looping over K (common dim)
+                for k in 0..self.shape.k {
+                    for m in 0..self.shape.m {
+                        match self.read(&self.left, &mut left_op, batch) {
+                            Some(data) => a_column.push(data),
+                            None if batch == 0 && k == 0 && m == 0 => return,
+                            None => {
+                                panic!("Unexpected termination of left stream in matmul ID: {:?} at time {:?} on iteration {batch}, {k}, {m}", self.id, self.time.tick());
+                            }
+                        }
+                    }
+                    for n in 0..self.shape.n {
+                        match self.read(&self.right, &mut right_op, batch) {
+                            Some(data) => b_row.push(data),
+                            None => {
+                                panic!("Unexpected termination of right stream in matmul ID: {:?} at time {:?} on iteration {batch}, {k}, {n}", self.id, self.time.tick());
+                            }
+                        }
+                    }
+                    for (m, a) in a_column.iter().enumerate() {
+                        for (n, b) in b_row.iter().enumerate() {
+                            let partial = &mut partials[m * self.shape.n + n];
+                            *partial = (self.mac)(a.clone(), b.clone(), *partial);
+                            if self.timing.issues_after(n, self.shape.n) {
+                                self.time.incr_cycles(self.timing.dot_ii);
+                            }
+                        }
+                    }
+                    a_column.clear();
+                    b_row.clear();
+                }
+                // Drain the partial output buffer, one element per cycle.
+                for (i, partial) in partials.into_iter().enumerate() {
+                    self.emit(partial + bias[i % self.shape.n], &mut epilogue_free);
+                    self.time.incr_cycles(1);
+                }
+            }
+        }
+    }
+
+    fn row_wise_matmul(&self) {
+        let mut epilogue_free = 0;
+        let (mut left_op, mut right_op) = self.operands();
+        loop {
+            for batch in 0..self.shape.batch {
+                let bias = match self.start_batch(batch, &mut left_op, &mut right_op) {
+                    Some(bias) => bias,
+                    None => return,
+                };
+                // Looping over M
+                for m in 0..self.shape.m {
+                    self.time.incr_cycles(self.timing.reset_time);
+                    let mut partials = vec![OutputT::zero(); self.shape.n];
                     // Looping over K (common dim)
                     for k in 0..self.shape.k {
-                        let _ = self.left.peek_next(&self.time);
-                        let _ = self.right.peek_next(&self.time);
-                        match (
-                            self.left.dequeue(&self.time),
-                            self.right.dequeue(&self.time),
-                        ) {
-                            (
-                                Ok(ChannelElement { time: _, data: a }),
-                                Ok(ChannelElement { time: _, data: b }),
-                            ) => {
-                                // Perform a MAC
-                                accum = (self.mac)(a, b, accum);
-                            }
-                            (_, Err(_)) | (Err(_), _) if (m == 0 && n == 0 && k == 0) => {
-                                // Finished all of our iterations
-                                return;
+                        let a = match self.read(&self.left, &mut left_op, batch) {
+                            Some(data) => data,
+                            None if batch == 0 && m == 0 && k == 0 => return,
+                            None => {
+                                panic!("Unexpected termination of left stream in matmul ID: {:?} at time {:?} on iteration {batch}, {m}, {k}", self.id, self.time.tick());
                             }
-                            _ => {
-                                panic!("Unexpected termination of streams in matmul ID: {:?} at time {:?} on iteration {m}, {n}, {k}", self.id, self.time.tick());
+                        };
+                        // Scale row k of B into the partial output row
+                        for (n, partial) in partials.iter_mut().enumerate() {
+                            let b = match self.read(&self.right, &mut right_op, batch) {
+                                Some(data) => data,
+                                None => {
+                                    panic!("Unexpected termination of right stream in matmul ID: {:?} at time {:?} on iteration {batch}, {m}, {k}, {n}", self.id, self.time.tick());
+                                }
+                            };
+                            *partial = (self.mac)(a.clone(), b, *partial);
+                            if self.timing.issues_after(n, self.shape.n) {
+                                self.time.incr_cycles(self.timing.dot_ii);
                             }
                         }
+                    }
+                    // Drain the partial output row, one element per cycle.
+                    for (partial, bias) in partials.into_iter().zip(bias.iter()) {
+                        self.emit(partial + *bias, &mut epilogue_free);
+                        self.time.incr_cycles(1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Loads `columns` columns of B (rows of B^T) into the weight buffer.
+    /// Returns false if the right stream ended before the first element.
+    fn load_weights(&self, buffer: &mut Vec<InputT>, columns: usize, load_ii: u64) -> bool {
+        buffer.clear();
+        for i in 0..columns * self.shape.k {
+            match self.right.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => buffer.push(data),
+                Err(_) if i == 0 => return false,
+                Err(_) => {
+                    panic!("Unexpected termination of right stream in matmul ID: {:?} at time {:?} while loading element {i} of the weight buffer", self.id, self.time.tick());
+                }
+            }
+            self.time.incr_cycles(load_ii);
+        }
+        true
+    }
 
-                        self.time.incr_cycles(self.timing.dot_ii);
+    fn weight_stationary_matmul(&self, capacity: usize, load_ii: u64) {
+        let mut epilogue_free = 0;
+        let (mut left_op, mut right_op) = self.operands();
+        // Columns of B that fit in the weight buffer at once
+        let tile = (capacity / self.shape.k).min(self.shape.n);
+        let fits = tile == self.shape.n;
+        // A broadcast B stays in the weight buffer for the whole batch.
+        let reload_right = self.shape.broadcast != BatchBroadcast::Right;
+        let mut left_buffer = Vec::with_capacity(self.shape.k);
+        let mut right_buffer = Vec::with_capacity(self.shape.k * tile);
+        loop {
+            for batch in 0..self.shape.batch {
+                let bias = match self.start_batch(batch, &mut left_op, &mut right_op) {
+                    Some(bias) => bias,
+                    None => return,
+                };
+                // Load all of B^T before any compute happens.
+                if fits
+                    && (batch == 0 || reload_right)
+                    && !self.load_weights(&mut right_buffer, self.shape.n, load_ii)
+                {
+                    if batch == 0 {
+                        return;
                     }
-                    self.output
-                        .enqueue(
-                            &self.time,
-                            ChannelElement {
-                                time: self.time.tick() + self.timing.dot_latency,
-                                data: accum,
-                            },
-                        )
-                        .unwrap_or_else(|_| {
-                            panic!(
-                                "Unexpected termination of output channel on Matmul {:?}",
-                                self.id
+                    panic!("Unexpected termination of right stream in matmul ID: {:?} at time {:?} on batch {batch}", self.id, self.time.tick());
+                }
+                // Loop over M
+                for m in 0..self.shape.m {
+                    for n in 0..self.shape.n {
+                        // Otherwise every row of A goes through B a buffer's worth at a time.
+                        if !fits
+                            && n % tile == 0
+                            && !self.load_weights(
+                                &mut right_buffer,
+                                tile.min(self.shape.n - n),
+                                load_ii,
                             )
-                        });
+                        {
+                            if batch == 0 && m == 0 && n == 0 {
+                                return;
+                            }
+                            panic!("Unexpected termination of right stream in matmul ID: {:?} at time {:?} on iteration {batch}, {m}, {n}", self.id, self.time.tick());
+                        }
+                        let should_populate_buffer = n == 0;
+                        let mut accum = OutputT::zero();
+                        for k in 0..self.shape.k {
+                            if should_populate_buffer {
+                                match self.read(&self.left, &mut left_op, batch) {
+                                    Some(data) => left_buffer.push(data),
+                                    None => {
+                                        panic!("Unexpected termination of left stream in matmul ID: {:?} at time {:?} on iteration {batch}, {m}, {n}, {k}", self.id, self.time.tick());
+                                    }
+                                }
+                            }
+                            let left_data = left_buffer[k].clone();
+                            let right_data = right_buffer[(n % tile) * self.shape.k + k].clone();
+                            accum = (self.mac)(left_data, right_data, accum);
+                            if self.timing.issues_after(k, self.shape.k) {
+                                self.time.incr_cycles(self.timing.dot_ii);
+                            }
+                        }
+                        self.emit(accum + bias[n], &mut epilogue_free);
+                    }
+                    left_buffer.clear();
                 }
             }
         }
     }
 }
 
-impl<InputT, OutputT, MacT> Context for Matmul<InputT, OutputT, MacT>
+impl<InputT, OutputT, MacT, EpilogueT> Context for Matmul<InputT, OutputT, MacT, EpilogueT>
 where
     InputT: DAMType,
     OutputT: DAMType + num::Float,
     MacT: Fn(InputT, InputT, OutputT) -> OutputT + Sync + Send,
+    EpilogueT: Fn(OutputT) -> OutputT + Sync + Send,
 {
     fn run(&mut self) {
         match self.behavior {
             MatmulBehavior::Buffered => self.buffered_matmul(),
             MatmulBehavior::Repeated => self.repeated_matmul(),
+            MatmulBehavior::OuterProduct => self.outer_product_matmul(),
+            MatmulBehavior::RowWise => self.row_wise_matmul(),
+            MatmulBehavior::WeightStationary { capacity, load_ii } => {
+                self.weight_stationary_matmul(capacity, load_ii)
+            }
         }
     }
 }
@@ -203,6 +679,7 @@ mod tests {
     use ndarray::ArcArray;
 
     use super::*;
+    use crate::templates::{BroadcastSender, Map, MapTimings};
 
     const CHAN_DEPTH: usize = 8;
 
@@ -212,21 +689,44 @@ mod tests {
         shape: ShapeInfo,
         outer_iterations: usize,
     ) {
-        // generate the input matrices
-        let a_matrices = (0..outer_iterations)
+        run_batched_test(behavior, timing, shape, outer_iterations, false);
+    }
+
+    fn run_batched_test(
+        behavior: MatmulBehavior,
+        timing: MatmulTiming,
+        shape: ShapeInfo,
+        outer_iterations: usize,
+        with_bias: bool,
+    ) {
+        // generate the input matrices, broadcast operands only once per batch
+        let matrices = |broadcast| match shape.broadcast == broadcast {
+            true => outer_iterations,
+            false => outer_iterations * shape.batch,
+        };
+        let a_matrices = (0..matrices(BatchBroadcast::Left))
             .map(|_| ArcArray::from_shape_simple_fn([shape.m, shape.k], fastrand::f32))
             .collect::<Vec<_>>();
-        let b_matrices = (0..outer_iterations)
+        let b_matrices = (0..matrices(BatchBroadcast::Right))
             .map(|_| ArcArray::from_shape_simple_fn([shape.k, shape.n], fastrand::f32))
             .collect::<Vec<_>>();
+        let biases = (0..outer_iterations * shape.batch)
+            .map(|_| match with_bias {
+                true => ArcArray::from_shape_simple_fn(shape.n, fastrand::f32),
+                false => ArcArray::zeros(shape.n),
+            })
+            .collect::<Vec<_>>();
 
         let mut builder = ProgramBuilder::default();
         let (a_snd, a_recv) = builder.bounded(CHAN_DEPTH);
         let (b_snd, b_recv) = builder.bounded(CHAN_DEPTH);
         let (c_snd, c_recv) = builder.bounded(CHAN_DEPTH);
 
+        // Left operand ordering
         match behavior {
-            MatmulBehavior::Buffered => {
+            MatmulBehavior::Buffered
+            | MatmulBehavior::RowWise
+            | MatmulBehavior::WeightStationary { .. } => {
                 // Simpler case because we can write A as-is
                 builder.add_child(GeneratorContext::new(
                     || a_matrices.iter().flat_map(|mat| mat.into_iter()).copied(),
@@ -250,33 +750,98 @@ mod tests {
                     a_snd,
                 ));
             }
+            MatmulBehavior::OuterProduct => {
+                // A is read column by column, once
+                builder.add_child(GeneratorContext::new(
+                    || {
+                        a_matrices
+                            .iter()
+                            .flat_map(|mat| mat.t().into_iter())
+                            .copied()
+                    },
+                    a_snd,
+                ));
+            }
         }
 
-        builder.add_child(GeneratorContext::new(
-            || {
-                b_matrices.iter().flat_map(|mat_b| {
-                    (0..shape.m).flat_map(move |_| {
-                        mat_b.t().iter().copied().collect::<Vec<_>>().into_iter()
-                    })
-                })
-            },
-            b_snd,
-        ));
+        // Right operand ordering
+        match behavior {
+            MatmulBehavior::Buffered | MatmulBehavior::Repeated => {
+                // B^T, once per row of A
+                builder.add_child(GeneratorContext::new(
+                    || {
+                        b_matrices.iter().flat_map(|mat_b| {
+                            (0..shape.m).flat_map(move |_| {
+                                mat_b.t().iter().copied().collect::<Vec<_>>().into_iter()
+                            })
+                        })
+                    },
+                    b_snd,
+                ));
+            }
+            MatmulBehavior::WeightStationary { .. } => {
+                // B^T, once if it fits in the weight buffer, otherwise once per row of A
+                let (passes, b_matrices) = (behavior.right_passes(&shape), &b_matrices);
+                builder.add_child(GeneratorContext::new(
+                    move || {
+                        b_matrices.iter().flat_map(move |mat_b| {
+                            (0..passes).flat_map(move |_| {
+                                mat_b.t().iter().copied().collect::<Vec<_>>().into_iter()
+                            })
+                        })
+                    },
+                    b_snd,
+                ));
+            }
+            MatmulBehavior::OuterProduct => {
+                // B is read row by row, once
+                builder.add_child(GeneratorContext::new(
+                    || b_matrices.iter().flat_map(|mat| mat.into_iter()).copied(),
+                    b_snd,
+                ));
+            }
+            MatmulBehavior::RowWise => {
+                // B as-is, once per row of A
+                builder.add_child(GeneratorContext::new(
+                    || {
+                        b_matrices.iter().flat_map(|mat_b| {
+                            (0..shape.m).flat_map(move |_| {
+                                mat_b.iter().copied().collect::<Vec<_>>().into_iter()
+                            })
+                        })
+                    },
+                    b_snd,
+                ));
+            }
+        }
         // The matmul node
-        builder.add_child(Matmul::new(
-            timing,
-            behavior,
-            shape,
-            a_recv,
-            b_recv,
-            c_snd,
-            |a, b, c| a * b + c,
-        ));
+        let matmul = Matmul::new(timing, behavior, shape, a_recv, b_recv, c_snd, |a, b, c| {
+            a * b + c
+        });
+        if with_bias {
+            let (bias_snd, bias_recv) = builder.bounded(CHAN_DEPTH);
+            builder.add_child(GeneratorContext::new(
+                || biases.iter().flat_map(|bias| bias.into_iter()).copied(),
+                bias_snd,
+            ));
+            builder.add_child(matmul.with_bias(bias_recv));
+        } else {
+            builder.add_child(matmul);
+        }
 
         builder.add_child(ApproxCheckerContext::new(
             || {
-                a_matrices.iter().zip(b_matrices.iter()).flat_map(|(a, b)| {
-                    let gold = a.dot(b);
+                let a_index = move |i: usize| match shape.broadcast {
+                    BatchBroadcast::Left => i / shape.batch,
+                    _ => i,
+                };
+                let b_index = move |i: usize| match shape.broadcast {
+                    BatchBroadcast::Right => i / shape.batch,
+                    _ => i,
+                };
+                let (a_matrices, b_matrices) = (&a_matrices, &b_matrices);
+                biases.iter().enumerate().flat_map(move |(i, bias)| {
+                    let gold = a_matrices[a_index(i)].dot(&b_matrices[b_index(i)]) + bias;
                     gold.into_iter()
                 })
             },
@@ -299,11 +864,16 @@ mod tests {
                 dot_latency: 1,
                 dot_ii: 1,
                 reset_time: 0,
+                vector_width: 1,
+                reduction_latency: 0,
+                batch_reset_time: 0,
             },
             ShapeInfo {
                 m: 512,
                 n: 32,
                 k: 16,
+                batch: 1,
+                broadcast: BatchBroadcast::None,
             },
             4,
         );
@@ -317,13 +887,270 @@ mod tests {
                 dot_latency: 1,
                 dot_ii: 1,
                 reset_time: 0,
+                vector_width: 1,
+                reduction_latency: 0,
+                batch_reset_time: 0,
             },
             ShapeInfo {
                 m: 512,
                 n: 32,
                 k: 16,
+                batch: 1,
+                broadcast: BatchBroadcast::None,
+            },
+            4,
+        );
+    }
+
+    #[test]
+    fn run_vectorized() {
+        // K is deliberately not a multiple of the vector width.
+        for behavior in [MatmulBehavior::Buffered, MatmulBehavior::Repeated] {
+            run_test(
+                behavior,
+                MatmulTiming {
+                    dot_latency: 1,
+                    dot_ii: 1,
+                    reset_time: 0,
+                    vector_width: 4,
+                    reduction_latency: 2,
+                    batch_reset_time: 0,
+                },
+                ShapeInfo {
+                    m: 64,
+                    n: 32,
+                    k: 18,
+                    batch: 1,
+                    broadcast: BatchBroadcast::None,
+                },
+                2,
+            );
+        }
+    }
+
+    #[test]
+    fn run_outer_product() {
+        run_test(
+            MatmulBehavior::OuterProduct,
+            MatmulTiming {
+                dot_latency: 1,
+                dot_ii: 1,
+                reset_time: 0,
+                vector_width: 8,
+                reduction_latency: 0,
+                batch_reset_time: 0,
+            },
+            ShapeInfo {
+                m: 64,
+                n: 32,
+                k: 16,
+                batch: 1,
+                broadcast: BatchBroadcast::None,
             },
             4,
         );
     }
+
+    #[test]
+    fn run_row_wise() {
+        run_test(
+            MatmulBehavior::RowWise,
+            MatmulTiming {
+                dot_latency: 1,
+                dot_ii: 1,
+                reset_time: 0,
+                vector_width: 8,
+                reduction_latency: 0,
+                batch_reset_time: 0,
+            },
+            ShapeInfo {
+                m: 64,
+                n: 32,
+                k: 16,
+                batch: 1,
+                broadcast: BatchBroadcast::None,
+            },
+            4,
+        );
+    }
+
+    #[test]
+    fn run_weight_stationary() {
+        let shape = ShapeInfo {
+            m: 64,
+            n: 32,
+            k: 16,
+            batch: 1,
+            broadcast: BatchBroadcast::None,
+        };
+        run_test(
+            MatmulBehavior::WeightStationary {
+                capacity: shape.k * shape.n,
+                load_ii: 1,
+            },
+            MatmulTiming {
+                dot_latency: 1,
+                dot_ii: 1,
+                reset_time: 0,
+                vector_width: 1,
+                reduction_latency: 0,
+                batch_reset_time: 0,
+            },
+            shape,
+            4,
+        );
+    }
+
+    #[test]
+    fn run_weight_reloads() {
+        // Only 12 of the 32 columns of B fit, so they are reloaded in three tiles for every row.
+        let shape = ShapeInfo {
+            m: 16,
+            n: 32,
+            k: 16,
+            batch: 1,
+            broadcast: BatchBroadcast::None,
+        };
+        run_test(
+            MatmulBehavior::WeightStationary {
+                capacity: shape.k * 12,
+                load_ii: 1,
+            },
+            MatmulTiming {
+                dot_latency: 1,
+                dot_ii: 1,
+                reset_time: 0,
+                vector_width: 1,
+                reduction_latency: 0,
+                batch_reset_time: 0,
+            },
+            shape,
+            2,
+        );
+    }
+
+    #[test]
+    fn run_fused_epilogue() {
+        let shape = ShapeInfo {
+            m: 32,
+            n: 32,
+            k: 16,
+            batch: 1,
+            broadcast: BatchBroadcast::None,
+        };
+        let timing = MatmulTiming {
+            dot_latency: 1,
+            dot_ii: 1,
+            reset_time: 0,
+            vector_width: 1,
+            reduction_latency: 0,
+            batch_reset_time: 0,
+        };
+        let a = ArcArray::from_shape_simple_fn([shape.m, shape.k], fastrand::f32);
+        let b = ArcArray::from_shape_simple_fn([shape.k, shape.n], fastrand::f32);
+        let reference = a.dot(&b).mapv(f32::exp);
+
+        // Matmul -> Map(exp) needs an extra context and channel, the fused version doesn't.
+        let run = |fused: bool| {
+            let mut builder = ProgramBuilder::default();
+            let (a_snd, a_recv) = builder.bounded(CHAN_DEPTH);
+            let (b_snd, b_recv) = builder.bounded(CHAN_DEPTH);
+            let (c_snd, c_recv) = builder.bounded(CHAN_DEPTH);
+            builder.add_child(GeneratorContext::new(|| a.clone().into_iter(), a_snd));
+            builder.add_child(GeneratorContext::new(
+                || {
+                    let b = b.clone();
+                    (0..shape.m).flat_map(move |_| b.t().iter().copied().collect::<Vec<_>>())
+                },
+                b_snd,
+            ));
+            let matmul = Matmul::new(
+                timing,
+                MatmulBehavior::Buffered,
+                shape,
+                a_recv,
+                b_recv,
+                c_snd,
+                |a, b, c| a * b + c,
+            );
+            let out_recv = if fused {
+                builder.add_child(matmul.with_epilogue(
+                    f32::exp,
+                    EpilogueTiming {
+                        initiation_interval: 1,
+                        latency: 4,
+                    },
+                ));
+                c_recv
+            } else {
+                builder.add_child(matmul);
+                let (exp_snd, exp_recv) = builder.bounded(CHAN_DEPTH);
+                builder.add_child(Map::new(
+                    vec![c_recv],
+                    BroadcastSender {
+                        targets: vec![exp_snd],
+                    },
+                    |x: &[f32]| x[0].exp(),
+                    MapTimings {
+                        initiation_interval: 1,
+                        latency: 4,
+                    },
+                ));
+                exp_recv
+            };
+            let expected = reference.clone();
+            builder.add_child(ApproxCheckerContext::new(
+                || expected.into_iter(),
+                out_recv,
+                // exp blows up the absolute error, so compare relative to the expected value.
+                |a, b| ((a - b) / b).abs() < 0.001,
+            ));
+            builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default())
+                .elapsed_cycles()
+        };
+        let fused = run(true);
+        let unfused = run(false);
+        dbg!(fused, unfused);
+        assert!(fused <= unfused);
+    }
+
+    #[test]
+    fn run_batched() {
+        let timing = MatmulTiming {
+            dot_latency: 1,
+            dot_ii: 1,
+            reset_time: 0,
+            vector_width: 4,
+            reduction_latency: 2,
+            batch_reset_time: 8,
+        };
+        for broadcast in [
+            BatchBroadcast::None,
+            BatchBroadcast::Left,
+            BatchBroadcast::Right,
+        ] {
+            let shape = ShapeInfo {
+                m: 16,
+                n: 8,
+                k: 12,
+                batch: 3,
+                broadcast,
+            };
+            for behavior in [
+                MatmulBehavior::Buffered,
+                MatmulBehavior::Repeated,
+                MatmulBehavior::OuterProduct,
+                MatmulBehavior::RowWise,
+                MatmulBehavior::WeightStationary {
+                    capacity: shape.k * shape.n,
+                    load_ii: 1,
+                },
+            ] {
+                run_batched_test(behavior, timing, shape, 2, true);
+            }
+        }
+    }
 }
@@ -16,3 +16,29 @@ mod zip;
 pub use zip::*;
 mod flatmap;
 pub use flatmap::*;
+mod topk;
+pub use topk::*;
+mod systolic;
+pub use systolic::*;
+mod tile_matmul;
+pub use tile_matmul::*;
+mod tiling;
+pub use tiling::*;
+mod transpose;
+pub use transpose::*;
+mod filter;
+pub use filter::*;
+mod arbiter;
+pub use arbiter::*;
+mod distributor;
+pub use distributor::*;
+mod reorder;
+pub use reorder::*;
+mod tree_reduce;
+pub use tree_reduce::*;
+mod prefix_scan;
+pub use prefix_scan::*;
+mod token;
+pub use token::*;
+mod vector;
+pub use vector::*;
@@ -0,0 +1,310 @@
+use dam::context_tools::*;
+
+use super::BroadcastSender;
+
+#[derive(Debug, Clone, Copy)]
+pub enum PrefixNetwork {
+    /// log2(W) levels, with W - 1 operators on the first level
+    KoggeStone,
+    /// 2 * log2(W) - 1 levels of up-sweep and down-sweep, with about 2W operators in total.
+    /// Being inclusive, this is the Brent-Kung form of the network.
+    Blelloch,
+}
+
+impl PrefixNetwork {
+    /// Levels of operators between the inputs and the last output of a W-wide chunk.
+    pub fn depth(&self, width: usize) -> u64 {
+        let levels = width.next_power_of_two().trailing_zeros() as u64;
+        match self {
+            PrefixNetwork::KoggeStone => levels,
+            PrefixNetwork::Blelloch => (2 * levels).saturating_sub(1),
+        }
+    }
+
+    /// Operators in the network for a W-wide chunk.
+    pub fn operators(&self, width: usize) -> usize {
+        let strides = (0..).map(|level| 1 << level).take_while(|d| *d < width);
+        match self {
+            PrefixNetwork::KoggeStone => strides.map(|d| width - d).sum(),
+            PrefixNetwork::Blelloch => {
+                let up: usize = strides.clone().map(|d| width / (2 * d)).sum();
+                // The down-sweep runs on every block size but the widest.
+                let down: usize = strides.skip(1).map(|d| (width - d / 2) / d).sum();
+                up + down
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PrefixScanTimings {
+    pub initiation_interval: u64,
+    /// Cycles per level of the prefix network
+    pub level_latency: u64,
+    /// Cycles to fold the total of the previous chunk into this one
+    pub carry_latency: u64,
+    pub reset_time: u64,
+}
+
+/// Inclusive scan that takes one element from each of its W inputs at a time and runs them
+/// through a prefix network, producing the same stream as a [super::Scan] with the same operator.
+/// Element i of a window comes from input i % W.
+/// Each chunk needs the total of the one before it, so the inputs stall if folding in the carry
+/// takes longer than the initiation interval.
+#[context_macro]
+pub struct PrefixScan<T: DAMType, OpF> {
+    reset_freq: usize,
+    inputs: Vec<Receiver<T>>,
+    output: BroadcastSender<T>,
+    op: OpF,
+    network: PrefixNetwork,
+    timings: PrefixScanTimings,
+}
+
+impl<T: DAMType, OpF> PrefixScan<T, OpF>
+where
+    Self: Context,
+{
+    /// `op` has to be associative.
+    pub fn new(
+        reset_freq: usize,
+        inputs: Vec<Receiver<T>>,
+        output: BroadcastSender<T>,
+        op: OpF,
+        network: PrefixNetwork,
+        timings: PrefixScanTimings,
+    ) -> Self {
+        assert!(!inputs.is_empty());
+        let s = Self {
+            reset_freq,
+            inputs,
+            output,
+            op,
+            network,
+            timings,
+            context_info: Default::default(),
+        };
+        s.inputs.iter().for_each(|chn| chn.attach_receiver(&s));
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType, OpF> PrefixScan<T, OpF>
+where
+    OpF: Fn(T, T) -> T,
+{
+    fn prefix(&self, values: Vec<T>) -> Vec<T> {
+        match self.network {
+            PrefixNetwork::KoggeStone => self.kogge_stone(values),
+            PrefixNetwork::Blelloch => self.blelloch(values),
+        }
+    }
+
+    /// At distance d, every element takes in the one d places before it.
+    fn kogge_stone(&self, mut values: Vec<T>) -> Vec<T> {
+        let mut distance = 1;
+        while distance < values.len() {
+            let previous = values.clone();
+            for i in distance..values.len() {
+                values[i] = (self.op)(previous[i - distance].clone(), previous[i].clone());
+            }
+            distance *= 2;
+        }
+        values
+    }
+
+    /// The up-sweep leaves the total of every aligned block of 2d elements at its end, and the
+    /// down-sweep fills in the middle of each block from the end of the half before it.
+    fn blelloch(&self, mut values: Vec<T>) -> Vec<T> {
+        let len = values.len();
+        let mut distance = 1;
+        while distance < len {
+            for i in (2 * distance - 1..len).step_by(2 * distance) {
+                values[i] = (self.op)(values[i - distance].clone(), values[i].clone());
+            }
+            distance *= 2;
+        }
+        while distance > 2 {
+            distance /= 2;
+            let half = distance / 2;
+            for i in (distance + half - 1..len).step_by(distance) {
+                values[i] = (self.op)(values[i - half].clone(), values[i].clone());
+            }
+        }
+        values
+    }
+}
+
+impl<T: DAMType, OpF> Context for PrefixScan<T, OpF>
+where
+    OpF: Sync + Send + Fn(T, T) -> T,
+{
+    fn run(&mut self) {
+        let network_latency = self.network.depth(self.inputs.len()) * self.timings.level_latency;
+        loop {
+            self.time.incr_cycles(self.timings.reset_time);
+            let mut carry: Option<T> = None;
+            // Cycle at which the total of the previous chunk is known
+            let mut carry_ready: u64 = 0;
+            let mut consumed = 0;
+            while consumed < self.reset_freq {
+                // Don't issue a chunk that would have to wait for its carry.
+                let issue = self.time.tick().time();
+                self.time
+                    .incr_cycles(carry_ready.saturating_sub(issue + network_latency));
+
+                let chunk = self.inputs.len().min(self.reset_freq - consumed);
+                let mut values = Vec::with_capacity(chunk);
+                for input in &self.inputs[..chunk] {
+                    match input.dequeue(&self.time) {
+                        Ok(ChannelElement { time: _, data }) => values.push(data),
+                        Err(_) if consumed == 0 && values.is_empty() => return,
+                        Err(_) => panic!(
+                            "Premature End of Receiver {:?} on PrefixScan {:?}",
+                            input.id(),
+                            self.id
+                        ),
+                    }
+                }
+                consumed += chunk;
+
+                let mut values = self.prefix(values);
+                let mut ready = self.time.tick().time() + network_latency;
+                if let Some(carry) = &carry {
+                    values = values
+                        .into_iter()
+                        .map(|value| (self.op)(carry.clone(), value))
+                        .collect();
+                    ready = ready.max(carry_ready) + self.timings.carry_latency;
+                }
+                carry_ready = ready;
+                carry = values.last().cloned();
+
+                for value in values {
+                    self.output
+                        .enqueue(
+                            &self.time,
+                            ChannelElement {
+                                time: self.time.tick()
+                                    + ready.saturating_sub(self.time.tick().time()),
+                                data: value,
+                            },
+                        )
+                        .unwrap_or_else(|_| {
+                            panic!("Premature End of Sender on PrefixScan {:?}", self.id)
+                        });
+                }
+                self.time.incr_cycles(self.timings.initiation_interval);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::{BroadcastSender, Scan, ScanTimings};
+
+    use super::{PrefixNetwork, PrefixScan, PrefixScanTimings};
+
+    #[test]
+    fn prefix_scan_test() {
+        const ROW: usize = 30;
+        const ROWS: u64 = 6;
+        let values = || (0..ROWS * ROW as u64).map(|x| x * 7 % 11);
+        let gold: Vec<u64> = values()
+            .collect::<Vec<_>>()
+            .chunks(ROW)
+            .flat_map(|row| {
+                row.iter()
+                    .scan(0, |running, x| {
+                        *running += *x;
+                        Some(*running)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // The sequential scan, for comparison.
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(16);
+        let (out_snd, out_rcv) = builder.bounded(16);
+        builder.add_child(GeneratorContext::new(values, in_snd));
+        builder.add_child(Scan::new(
+            ROW,
+            in_rcv,
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            |new: u64, old: Option<&u64>| old.map_or(new, |old| new + *old),
+            ScanTimings {
+                initiation_interval: 1,
+                latency: 1,
+                reset_time: 0,
+            },
+        ));
+        builder.add_child(CheckerContext::new(|| gold.iter().copied(), out_rcv));
+        let sequential = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default())
+            .elapsed_cycles()
+            .unwrap();
+
+        let mut elapsed = vec![];
+        for (width, network) in [
+            (1, PrefixNetwork::KoggeStone),
+            (8, PrefixNetwork::KoggeStone),
+            (8, PrefixNetwork::Blelloch),
+        ] {
+            let mut builder = ProgramBuilder::default();
+            let inputs = (0..width)
+                .map(|lane| {
+                    let (snd, rcv) = builder.bounded(16);
+                    builder.add_child(GeneratorContext::new(
+                        move || {
+                            values()
+                                .enumerate()
+                                .filter(move |(i, _)| i % ROW % width == lane)
+                                .map(|(_, x)| x)
+                        },
+                        snd,
+                    ));
+                    rcv
+                })
+                .collect();
+            let (out_snd, out_rcv) = builder.bounded(16);
+            builder.add_child(PrefixScan::new(
+                ROW,
+                inputs,
+                BroadcastSender {
+                    targets: vec![out_snd],
+                },
+                |a: u64, b: u64| a + b,
+                network,
+                PrefixScanTimings {
+                    initiation_interval: 1,
+                    level_latency: 1,
+                    carry_latency: 1,
+                    reset_time: 0,
+                },
+            ));
+            builder.add_child(CheckerContext::new(|| gold.iter().copied(), out_rcv));
+            let executed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+            elapsed.push(executed.elapsed_cycles().unwrap());
+        }
+        assert_eq!(PrefixNetwork::KoggeStone.operators(8), 17);
+        assert_eq!(PrefixNetwork::Blelloch.operators(8), 11);
+        dbg!(sequential, &elapsed);
+        assert!(elapsed[1] < sequential);
+        assert!(elapsed[1] <= elapsed[2]);
+    }
+}
@@ -1,11 +1,32 @@
 use dam::context_tools::*;
 
+use super::Predicated;
+
+#[derive(Debug, Clone, Copy)]
 pub struct ReduceTimings {
     pub initiation_interval: u64,
     pub latency: u64,
     pub reset_time: u64,
 }
 
+/// How the accumulator feeds back into the next update.
+#[derive(Debug, Clone, Copy)]
+pub enum Recurrence<T> {
+    /// The accumulator is ready for the next element right away.
+    Free,
+    /// The next update has to wait `latency` cycles for the previous one.
+    Serial { latency: u64 },
+    /// Elements are spread round-robin over several partial accumulators, each of which has to
+    /// wait `latency` cycles for its previous update. The partials are merged at the end of the
+    /// window by a tree of `combine`s, taking `combine_latency` cycles per level.
+    Interleaved {
+        accumulators: usize,
+        latency: u64,
+        combine_latency: u64,
+        combine: fn(T, T) -> T,
+    },
+}
+
 #[context_macro]
 pub struct Reduce<InT: DAMType, OutT: DAMType, UpdateT> {
     reset_freq: usize,
@@ -13,6 +34,9 @@ pub struct Reduce<InT: DAMType, OutT: DAMType, UpdateT> {
     output: Sender<OutT>,
     update_fn: UpdateT,
     timings: ReduceTimings,
+    skip: fn(&InT) -> bool,
+    recurrence: Recurrence<OutT>,
+    rows: usize,
 }
 
 impl<InT: DAMType, OutT: DAMType, UpdateT> Reduce<InT, OutT, UpdateT>
@@ -32,12 +56,42 @@ where
             output,
             update_fn,
             timings,
+            skip: |_| false,
+            recurrence: Recurrence::Free,
+            rows: 1,
             context_info: Default::default(),
         };
         s.input.attach_receiver(&s);
         s.output.attach_sender(&s);
         s
     }
+
+    /// Models the loop-carried dependency through the accumulator.
+    pub fn with_recurrence(mut self, recurrence: Recurrence<OutT>) -> Self {
+        if let Recurrence::Interleaved { accumulators, .. } = recurrence {
+            assert!(accumulators > 0);
+        }
+        self.recurrence = recurrence;
+        self
+    }
+
+    /// Takes the elements of `rows` independent windows round-robin, with element i going to
+    /// window i % rows, and keeps a separate accumulator for each. The results come out in
+    /// window order once all of them are done.
+    pub fn with_interleaved_rows(mut self, rows: usize) -> Self {
+        assert!(rows > 0);
+        self.rows = rows;
+        self
+    }
+}
+
+impl<T: DAMType, OutT: DAMType, UpdateT> Reduce<Predicated<T>, OutT, UpdateT> {
+    /// Invalid elements still take up a slot, but don't update the accumulator;
+    /// an invalid window produces OutT::default().
+    pub fn skip_invalid(mut self) -> Self {
+        self.skip = |input| !input.valid;
+        self
+    }
 }
 
 impl<InT: DAMType, OutT: DAMType, UpdateT> Context for Reduce<InT, OutT, UpdateT>
@@ -45,11 +99,24 @@ where
     UpdateT: Sync + Send + Fn(InT, Option<OutT>) -> OutT,
 {
     fn run(&mut self) {
+        let (accumulators, latency) = match self.recurrence {
+            Recurrence::Free => (1, 0),
+            Recurrence::Serial { latency } => (1, latency),
+            Recurrence::Interleaved {
+                accumulators,
+                latency,
+                ..
+            } => (accumulators, latency),
+        };
+        let slots = self.rows * accumulators;
         // Infinite loop to handle all inputs
         loop {
             self.time.incr_cycles(self.timings.reset_time);
-            let mut accum: Option<OutT> = None;
-            for iter in 0..self.reset_freq {
+            // Partial accumulators of row r are at r * accumulators..(r + 1) * accumulators
+            let mut partials: Vec<Option<OutT>> = vec![None; slots];
+            // Cycle at which each partial accumulator can take its next update
+            let mut ready = vec![0u64; slots];
+            for iter in 0..self.reset_freq * self.rows {
                 let input = match self.input.dequeue(&self.time) {
                     Ok(ChannelElement { time: _, data }) => data,
                     Err(_) if iter == 0 => return,
@@ -59,25 +126,50 @@ where
                         self.id
                     ),
                 };
-                let new_val = (self.update_fn)(input, accum);
-                accum = Some(new_val);
+                if !(self.skip)(&input) {
+                    let row = iter % self.rows;
+                    let lane = row * accumulators + (iter / self.rows) % accumulators;
+                    self.time
+                        .incr_cycles(ready[lane].saturating_sub(self.time.tick().time()));
+                    partials[lane] = Some((self.update_fn)(input, partials[lane].take()));
+                    ready[lane] = self.time.tick().time() + latency;
+                }
                 self.time.incr_cycles(self.timings.initiation_interval);
             }
-            self.output
-                .enqueue(
-                    &self.time,
-                    ChannelElement {
-                        time: self.time.tick() + self.timings.latency,
-                        data: accum.unwrap(),
-                    },
-                )
-                .unwrap_or_else(|_| {
-                    panic!(
-                        "Premature End of Sender {:?} on Reduce {:?}",
-                        self.output.id(),
-                        self.id
+
+            let now = self.time.tick().time();
+            let mut partials = partials.into_iter();
+            for row_ready in ready.chunks(accumulators) {
+                let mut done = row_ready.iter().copied().fold(now, u64::max);
+                let row_partials = partials.by_ref().take(accumulators);
+                let accum = match self.recurrence {
+                    Recurrence::Interleaved {
+                        combine_latency,
+                        combine,
+                        ..
+                    } => {
+                        done += accumulators.next_power_of_two().trailing_zeros() as u64
+                            * combine_latency;
+                        row_partials.flatten().reduce(combine)
+                    }
+                    _ => row_partials.last().unwrap(),
+                };
+                self.output
+                    .enqueue(
+                        &self.time,
+                        ChannelElement {
+                            time: self.time.tick() + (done - now) + self.timings.latency,
+                            data: accum.unwrap_or_default(),
+                        },
                     )
-                });
+                    .unwrap_or_else(|_| {
+                        panic!(
+                            "Premature End of Sender {:?} on Reduce {:?}",
+                            self.output.id(),
+                            self.id
+                        )
+                    });
+            }
         }
     }
 }
@@ -89,7 +181,7 @@ mod tests {
         utility_contexts::{CheckerContext, GeneratorContext},
     };
 
-    use super::Reduce;
+    use super::{Recurrence, Reduce, ReduceTimings};
 
     #[test]
     fn reduce_test() {
@@ -128,4 +220,53 @@ mod tests {
             .elapsed_cycles();
         dbg!(elapsed);
     }
+
+    #[test]
+    fn recurrence_test() {
+        const ROW: u64 = 32;
+        const ROWS: u64 = 8;
+        let mut elapsed = vec![];
+        for recurrence in [
+            Recurrence::Free,
+            Recurrence::Serial { latency: 4 },
+            Recurrence::Interleaved {
+                accumulators: 4,
+                latency: 4,
+                combine_latency: 2,
+                combine: |a, b| a + b,
+            },
+        ] {
+            let mut builder = ProgramBuilder::default();
+            let (in_snd, in_rcv) = builder.bounded(16);
+            let (out_snd, out_rcv) = builder.bounded(16);
+            builder.add_child(GeneratorContext::new(|| 0..ROW * ROWS, in_snd));
+            builder.add_child(
+                Reduce::new(
+                    ROW as usize,
+                    in_rcv,
+                    out_snd,
+                    |new, old: Option<u64>| old.unwrap_or(0) + new,
+                    ReduceTimings {
+                        initiation_interval: 1,
+                        latency: 1,
+                        reset_time: 0,
+                    },
+                )
+                .with_recurrence(recurrence),
+            );
+            builder.add_child(CheckerContext::new(
+                || (0..ROWS).map(|row| (row * ROW..(row + 1) * ROW).sum()),
+                out_rcv,
+            ));
+            let executed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+            elapsed.push(executed.elapsed_cycles().unwrap());
+        }
+        dbg!(&elapsed);
+        // A serial accumulator only takes every 4th cycle, interleaving hides all but the combine.
+        assert!(elapsed[1] > 3 * elapsed[0]);
+        assert!(elapsed[2] < elapsed[1] / 2);
+    }
 }
@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+
+use dam::{channel::PeekResult, context_tools::*};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReorderTimings {
+    /// Cycles between emitted elements
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+/// Recombines streams that a [super::Distributor] split into blocks: block i of the output is
+/// read from input i % N. Elements of later blocks that arrive early are held in a buffer of
+/// `capacity` elements, so that the other inputs don't stall while waiting on a slower one.
+#[context_macro]
+pub struct ReorderBuffer<T: DAMType> {
+    inputs: Vec<Receiver<T>>,
+    output: Sender<T>,
+    block: usize,
+    capacity: usize,
+    timings: ReorderTimings,
+}
+
+impl<T: DAMType> ReorderBuffer<T>
+where
+    Self: Context,
+{
+    pub fn new(
+        inputs: Vec<Receiver<T>>,
+        output: Sender<T>,
+        block: usize,
+        capacity: usize,
+        timings: ReorderTimings,
+    ) -> Self {
+        assert!(!inputs.is_empty());
+        assert!(block > 0);
+        let s = Self {
+            inputs,
+            output,
+            block,
+            capacity,
+            timings,
+            context_info: Default::default(),
+        };
+        s.inputs.iter().for_each(|chn| chn.attach_receiver(&s));
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType> Context for ReorderBuffer<T> {
+    fn run(&mut self) {
+        let mut buffers: Vec<VecDeque<T>> = self.inputs.iter().map(|_| VecDeque::new()).collect();
+        let mut held = 0;
+        let (mut current, mut left) = (0, self.block);
+        loop {
+            let now = self.time.tick().time();
+            let mut ready = vec![];
+            // Nothing that can be taken arrives before the next element or before every sender
+            // reaches this.
+            let mut next_arrival = None;
+            let mut closed = 0;
+            for (input, chn) in self.inputs.iter().enumerate() {
+                let time = match chn.peek() {
+                    PeekResult::Something(ChannelElement { time, data: _ })
+                        if time.time() <= now =>
+                    {
+                        ready.push(input);
+                        continue;
+                    }
+                    PeekResult::Something(ChannelElement { time, data: _ }) => time.time(),
+                    PeekResult::Nothing(time) => time.time(),
+                    PeekResult::Closed => {
+                        closed += 1;
+                        continue;
+                    }
+                };
+                if (input == current || held < self.capacity)
+                    && next_arrival.is_none_or(|(t, _)| time < t)
+                {
+                    next_arrival = Some((time, input));
+                }
+            }
+
+            // The current block comes out of the buffer first, then straight from its input.
+            let data = match buffers[current].pop_front() {
+                Some(data) => {
+                    held -= 1;
+                    Some(data)
+                }
+                None if ready.contains(&current) => {
+                    Some(self.inputs[current].dequeue(&self.time).unwrap().data)
+                }
+                None => None,
+            };
+            // Meanwhile, one early element of a later block can be written into the buffer.
+            let early = ready.iter().find(|input| **input != current);
+            let stored = match early {
+                Some(&input) if held < self.capacity => {
+                    let data = self.inputs[input].dequeue(&self.time).unwrap().data;
+                    buffers[input].push_back(data);
+                    held += 1;
+                    true
+                }
+                _ => false,
+            };
+
+            match data {
+                Some(data) => {
+                    self.output
+                        .enqueue(
+                            &self.time,
+                            ChannelElement {
+                                time: self.time.tick() + self.timings.latency,
+                                data,
+                            },
+                        )
+                        .unwrap_or_else(|_| {
+                            panic!("Premature End of Sender on ReorderBuffer {:?}", self.id)
+                        });
+                    left -= 1;
+                    if left == 0 {
+                        current = (current + 1) % self.inputs.len();
+                        left = self.block;
+                    }
+                    self.time.incr_cycles(self.timings.initiation_interval);
+                }
+                None if stored => self.time.incr_cycles(1),
+                None if closed == self.inputs.len() => {
+                    if held > 0 || left != self.block {
+                        panic!(
+                            "Premature End of Receiver {:?} on ReorderBuffer {:?}",
+                            self.inputs[current].id(),
+                            self.id
+                        );
+                    }
+                    return;
+                }
+                None => match next_arrival {
+                    Some((time, _)) if time > now => self.time.incr_cycles(time - now),
+                    // That sender hasn't caught up yet, so wait on it.
+                    Some((_, input)) => {
+                        let _ = self.inputs[input].peek_next(&self.time);
+                    }
+                    None => panic!(
+                        "Premature End of Receiver {:?} on ReorderBuffer {:?}",
+                        self.inputs[current].id(),
+                        self.id
+                    ),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::{
+        BroadcastSender, DistributePolicy, DistributeTimings, Distributor, Map, MapTimings,
+    };
+
+    use super::{ReorderBuffer, ReorderTimings};
+
+    #[test]
+    fn reorder_test() {
+        const LANES: usize = 3;
+        const BLOCK: usize = 4;
+        const LEN: u64 = 96;
+        let mut elapsed = vec![];
+        for capacity in [0, 2 * BLOCK, LANES * BLOCK] {
+            let mut builder = ProgramBuilder::default();
+            let (in_snd, in_rcv) = builder.bounded(4);
+            builder.add_child(GeneratorContext::new(|| 0..LEN, in_snd));
+            let mut lanes = vec![];
+            let mut outputs = vec![];
+            for lane in 0..LANES {
+                let (snd, rcv) = builder.bounded(4);
+                let (out_snd, out_rcv) = builder.bounded(4);
+                lanes.push(snd);
+                outputs.push(out_rcv);
+                // Lanes with a higher index are slower.
+                builder.add_child(Map::new(
+                    vec![rcv],
+                    BroadcastSender {
+                        targets: vec![out_snd],
+                    },
+                    |x: &[u64]| x[0],
+                    MapTimings {
+                        initiation_interval: 1,
+                        latency: 1 + 8 * lane as u64,
+                    },
+                ));
+            }
+            builder.add_child(Distributor::new(
+                in_rcv,
+                lanes,
+                DistributePolicy::Blocks(BLOCK),
+                DistributeTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+            ));
+            let (out_snd, out_rcv) = builder.bounded(4);
+            builder.add_child(ReorderBuffer::new(
+                outputs,
+                out_snd,
+                BLOCK,
+                capacity,
+                ReorderTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                },
+            ));
+            builder.add_child(CheckerContext::new(|| 0..LEN, out_rcv));
+            let executed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+            elapsed.push(executed.elapsed_cycles().unwrap());
+        }
+        dbg!(&elapsed);
+    }
+}
@@ -2,25 +2,67 @@ use dam::context_tools::*;
 
 use super::BroadcastSender;
 
+#[derive(Debug, Clone, Copy)]
+pub struct RepeatTimings {
+    /// Cycles per emitted copy
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+enum RepeatCount {
+    Fixed(usize),
+    /// One count per input element, e.g. the length of each row of a causal mask.
+    Stream(Receiver<usize>),
+}
+
 #[context_macro]
 pub struct Repeat<InT: DAMType> {
     input: Receiver<InT>,
     output: BroadcastSender<InT>,
-    repeats: usize,
+    repeats: RepeatCount,
+    timings: RepeatTimings,
 }
 
 impl<InT: DAMType> Repeat<InT>
 where
     Self: Context,
 {
-    pub fn new(input: Receiver<InT>, output: BroadcastSender<InT>, repeats: usize) -> Self {
+    pub fn new(
+        input: Receiver<InT>,
+        output: BroadcastSender<InT>,
+        repeats: usize,
+        timings: RepeatTimings,
+    ) -> Self {
+        Self::build(input, output, RepeatCount::Fixed(repeats), timings)
+    }
+
+    /// Repeats every input element as many times as the matching element of `counts`.
+    pub fn with_counts(
+        input: Receiver<InT>,
+        counts: Receiver<usize>,
+        output: BroadcastSender<InT>,
+        timings: RepeatTimings,
+    ) -> Self {
+        Self::build(input, output, RepeatCount::Stream(counts), timings)
+    }
+
+    fn build(
+        input: Receiver<InT>,
+        output: BroadcastSender<InT>,
+        repeats: RepeatCount,
+        timings: RepeatTimings,
+    ) -> Self {
         let s = Self {
             input,
             output,
             repeats,
+            timings,
             context_info: Default::default(),
         };
         s.input.attach_receiver(&s);
+        if let RepeatCount::Stream(counts) = &s.repeats {
+            counts.attach_receiver(&s);
+        }
         s.output.attach_sender(&s);
         s
     }
@@ -31,16 +73,28 @@ impl<InT: DAMType> Context for Repeat<InT> {
         loop {
             match self.input.dequeue(&self.time) {
                 Ok(ChannelElement { time: _, data }) => {
-                    for _ in 0..self.repeats {
+                    let repeats = match &self.repeats {
+                        RepeatCount::Fixed(repeats) => *repeats,
+                        RepeatCount::Stream(counts) => match counts.dequeue(&self.time) {
+                            Ok(ChannelElement { time: _, data }) => data,
+                            Err(_) => panic!(
+                                "Premature End of Receiver {:?} on Repeat {:?}",
+                                counts.id(),
+                                self.id
+                            ),
+                        },
+                    };
+                    for _ in 0..repeats {
                         self.output
                             .enqueue(
                                 &self.time,
                                 ChannelElement {
-                                    time: self.time.tick() + 1,
+                                    time: self.time.tick() + self.timings.latency,
                                     data: data.clone(),
                                 },
                             )
                             .unwrap();
+                        self.time.incr_cycles(self.timings.initiation_interval);
                     }
                 }
                 Err(_) => return,
@@ -48,3 +102,48 @@ impl<InT: DAMType> Context for Repeat<InT> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::BroadcastSender;
+
+    use super::{Repeat, RepeatTimings};
+
+    #[test]
+    fn test_repeat_counts() {
+        // Row i of a causal mask has i + 1 elements.
+        const ROWS: usize = 16;
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(4);
+        let (count_snd, count_rcv) = builder.bounded(4);
+        let (out_snd, out_rcv) = builder.bounded(4);
+        builder.add_child(GeneratorContext::new(|| 0..ROWS as u64, in_snd));
+        builder.add_child(GeneratorContext::new(|| 1..=ROWS, count_snd));
+        builder.add_child(Repeat::with_counts(
+            in_rcv,
+            count_rcv,
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            RepeatTimings {
+                initiation_interval: 1,
+                latency: 2,
+            },
+        ));
+        builder.add_child(CheckerContext::new(
+            || (0..ROWS as u64).flat_map(|row| std::iter::repeat_n(row, row as usize + 1)),
+            out_rcv,
+        ));
+        let elapsed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default())
+            .elapsed_cycles();
+        dbg!(elapsed);
+    }
+}
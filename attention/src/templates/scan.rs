@@ -1,7 +1,8 @@
 use dam::context_tools::*;
 
-use super::BroadcastSender;
+use super::{BroadcastSender, Predicated};
 
+#[derive(Debug, Clone, Copy)]
 pub struct ScanTimings {
     pub initiation_interval: u64,
     pub latency: u64,
@@ -15,6 +16,9 @@ pub struct Scan<InT: DAMType, OutT: DAMType, UpdateT> {
     output: BroadcastSender<OutT>,
     update_fn: UpdateT,
     timings: ScanTimings,
+    skip: fn(&InT) -> bool,
+    recurrence_latency: u64,
+    rows: usize,
 }
 
 impl<InT: DAMType, OutT: DAMType, UpdateT> Scan<InT, OutT, UpdateT>
@@ -34,12 +38,40 @@ where
             output,
             update_fn,
             timings,
+            skip: |_| false,
+            recurrence_latency: 0,
+            rows: 1,
             context_info: Default::default(),
         };
         s.input.attach_receiver(&s);
         s.output.attach_sender(&s);
         s
     }
+
+    /// Every output feeds into the next update, so an update has to wait `latency` cycles for
+    /// the previous one.
+    pub fn with_recurrence(mut self, latency: u64) -> Self {
+        self.recurrence_latency = latency;
+        self
+    }
+
+    /// Takes the elements of `rows` independent windows round-robin, with element i going to
+    /// window i % rows, and keeps a separate accumulator for each. Updates to different windows
+    /// don't wait on each other, which hides up to `rows` cycles of recurrence latency.
+    pub fn with_interleaved_rows(mut self, rows: usize) -> Self {
+        assert!(rows > 0);
+        self.rows = rows;
+        self
+    }
+}
+
+impl<T: DAMType, OutT: DAMType, UpdateT> Scan<Predicated<T>, OutT, UpdateT> {
+    /// Invalid elements still take up a slot, but don't update the accumulator;
+    /// they repeat the last output, or OutT::default() at the start of a window.
+    pub fn skip_invalid(mut self) -> Self {
+        self.skip = |input| !input.valid;
+        self
+    }
 }
 
 impl<InT: DAMType, OutT: DAMType, UpdateT> Context for Scan<InT, OutT, UpdateT>
@@ -49,9 +81,11 @@ where
     fn run(&mut self) {
         // Infinite loop to handle all inputs
         loop {
-            let mut accum: Option<OutT> = None;
+            let mut accum: Vec<Option<OutT>> = vec![None; self.rows];
+            // Cycle at which each accumulator can take its next update
+            let mut ready = vec![0u64; self.rows];
             self.time.incr_cycles(self.timings.reset_time);
-            for iter in 0..self.reset_freq {
+            for iter in 0..self.reset_freq * self.rows {
                 let input = match self.input.dequeue(&self.time) {
                     Ok(ChannelElement { time: _, data }) => data,
                     Err(_) if iter == 0 => return,
@@ -61,7 +95,17 @@ where
                         self.id
                     ),
                 };
-                let new_val = (self.update_fn)(input, accum.as_ref());
+                let row = iter % self.rows;
+                let skip = (self.skip)(&input);
+                let new_val = match skip {
+                    true => accum[row].clone().unwrap_or_default(),
+                    false => {
+                        self.time
+                            .incr_cycles(ready[row].saturating_sub(self.time.tick().time()));
+                        ready[row] = self.time.tick().time() + self.recurrence_latency;
+                        (self.update_fn)(input, accum[row].as_ref())
+                    }
+                };
                 self.output
                     .enqueue(
                         &self.time,
@@ -71,7 +115,9 @@ where
                         },
                     )
                     .unwrap_or_else(|_| panic!("Premature End of Sender on Scan {:?}", self.id));
-                accum = Some(new_val);
+                if !skip {
+                    accum[row] = Some(new_val);
+                }
                 self.time.incr_cycles(self.timings.initiation_interval);
             }
         }
@@ -141,4 +187,51 @@ mod tests {
             .elapsed_cycles();
         dbg!(elapsed);
     }
+
+    #[test]
+    fn interleaved_scan_test() {
+        // Three windows of 10 elements, taken round-robin, with a slow accumulator.
+        const ROWS: usize = 3;
+        const LEN: usize = 10;
+        let inputs: Vec<u64> = (0..(2 * ROWS * LEN) as u64).collect();
+        let mut gold = vec![];
+        for group in inputs.chunks(ROWS * LEN) {
+            let mut sums = [0; ROWS];
+            for (i, x) in group.iter().enumerate() {
+                sums[i % ROWS] += x;
+                gold.push(sums[i % ROWS]);
+            }
+        }
+
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(16);
+        builder.add_child(GeneratorContext::new(|| inputs.into_iter(), in_snd));
+
+        let (out_snd, out_rcv) = builder.bounded(16);
+        builder.add_child(
+            Scan::new(
+                LEN,
+                in_rcv,
+                BroadcastSender {
+                    targets: vec![out_snd],
+                },
+                |new, old| match old {
+                    Some(old_val) => new + old_val,
+                    None => new,
+                },
+                super::ScanTimings {
+                    initiation_interval: 1,
+                    latency: 1,
+                    reset_time: 0,
+                },
+            )
+            .with_recurrence(ROWS as u64)
+            .with_interleaved_rows(ROWS),
+        );
+        builder.add_child(CheckerContext::new(|| gold.into_iter(), out_rcv));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
 }
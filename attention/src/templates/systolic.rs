@@ -0,0 +1,465 @@
+use dam::context_tools::*;
+
+use super::{BatchBroadcast, MatmulTiming, ShapeInfo};
+
+#[derive(Debug, Clone, Copy)]
+pub enum SystolicDataflow {
+    /// Each PE owns one output. A streams in from the left, B^T from the top, K is the streaming dimension.
+    OutputStationary,
+    /// Each PE holds one element of B. Rows of A stream through, partial sums flow down the columns.
+    WeightStationary,
+    /// Each PE holds one element of A. Columns of B stream through, partial sums flow down the columns.
+    InputStationary,
+}
+
+/// A grid of `rows` x `cols` processing elements.
+#[derive(Debug, Clone, Copy)]
+pub struct SystolicGrid {
+    pub rows: usize,
+    pub cols: usize,
+}
+
+/// Computes A: [M, K] x B[K, N] = C [M, N] on a systolic array.
+/// Consumes and produces the same streams as [super::MatmulBehavior::Buffered]:
+/// A is read once in row-major order, B^T is read once per row of A, and C is emitted row-major.
+///
+/// Rows of A are processed in passes: a pass covers as many rows as the grid maps onto M, and
+/// only that tile of A is held at a time. Within a pass every PE is simulated: operands hop one
+/// PE per `dot_ii`, each PE fires once all of its inputs have arrived, and results leave the
+/// grid through its bottom edge. C is then emitted in row-major order, each element no earlier
+/// than it has left the grid, so a stalled output also stalls the next pass.
+#[context_macro]
+pub struct SystolicMatmul<InputT, OutputT, MacT>
+where
+    InputT: DAMType,
+    OutputT: DAMType,
+{
+    timing: MatmulTiming,
+    dataflow: SystolicDataflow,
+    grid: SystolicGrid,
+    shape: ShapeInfo,
+    left: Receiver<InputT>,
+    right: Receiver<InputT>,
+    output: Sender<OutputT>,
+    mac: MacT,
+}
+
+/// An operand or result, along with the cycle it is available at.
+type Timed<T> = (T, u64);
+
+impl<InputT, OutputT, MacT> SystolicMatmul<InputT, OutputT, MacT>
+where
+    InputT: DAMType,
+    OutputT: DAMType + num::Float,
+    MacT: Fn(InputT, InputT, OutputT) -> OutputT + Sync + Send,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        timing: MatmulTiming,
+        dataflow: SystolicDataflow,
+        grid: SystolicGrid,
+        shape: ShapeInfo,
+        left: Receiver<InputT>,
+        right: Receiver<InputT>,
+        output: Sender<OutputT>,
+        mac: MacT,
+    ) -> Self {
+        assert!(grid.rows > 0 && grid.cols > 0);
+        assert_eq!(shape.batch, 1, "SystolicMatmul doesn't support batches");
+        assert_eq!(
+            shape.broadcast,
+            BatchBroadcast::None,
+            "SystolicMatmul doesn't replay broadcast operands"
+        );
+        let output = Self {
+            timing,
+            dataflow,
+            grid,
+            shape,
+            left,
+            right,
+            output,
+            mac,
+            context_info: Default::default(),
+        };
+        output.left.attach_receiver(&output);
+        output.right.attach_receiver(&output);
+        output.output.attach_sender(&output);
+        output
+    }
+
+    /// Number of rows of A covered by a single pass over the grid.
+    fn rows_per_pass(&self) -> usize {
+        match self.dataflow {
+            SystolicDataflow::OutputStationary => self.grid.rows,
+            SystolicDataflow::WeightStationary => self.grid.rows,
+            SystolicDataflow::InputStationary => self.grid.cols,
+        }
+    }
+
+    /// Each PE accumulates one output over all of K, with A hopping right and B^T hopping down.
+    /// Once a column of PEs is done, its results shift out through the bottom edge.
+    fn output_stationary(
+        &self,
+        a: &[Vec<Timed<InputT>>],
+        b_t: &[Vec<Timed<InputT>>],
+        out: &mut [Vec<Timed<OutputT>>],
+    ) {
+        let ii = self.timing.dot_ii;
+        let rows = a.len();
+        // The cycle each PE last fired at, or was freed at once its result left.
+        let mut fired = vec![vec![0; self.grid.cols]; rows];
+        for n0 in (0..self.shape.n).step_by(self.grid.cols) {
+            let cols = self.grid.cols.min(self.shape.n - n0);
+            let mut accum = vec![vec![OutputT::zero(); cols]; rows];
+            for k in 0..self.shape.k {
+                for i in 0..rows {
+                    for j in 0..cols {
+                        let (a_elem, a_arrival) = &a[i][k];
+                        let (b_elem, b_arrival) = &b_t[n0 + j][k];
+                        let a_in = if j == 0 {
+                            *a_arrival
+                        } else {
+                            fired[i][j - 1] + ii
+                        };
+                        let b_in = if i == 0 {
+                            *b_arrival
+                        } else {
+                            fired[i - 1][j] + ii
+                        };
+                        let ready = if k == 0 {
+                            fired[i][j]
+                        } else {
+                            fired[i][j] + ii
+                        };
+                        fired[i][j] = a_in.max(b_in).max(ready);
+                        accum[i][j] = (self.mac)(a_elem.clone(), b_elem.clone(), accum[i][j]);
+                    }
+                }
+            }
+            for j in 0..cols {
+                let done = (0..rows).map(|i| fired[i][j]).max().unwrap() + self.timing.dot_latency;
+                // The bottom row leaves first, the others shift down behind it.
+                for i in 0..rows {
+                    let exit = done + (rows - i) as u64 * ii;
+                    out[i][n0 + j] = (accum[i][j], exit);
+                    fired[i][j] = exit;
+                }
+            }
+        }
+    }
+
+    /// Each PE holds one element of a PxQ tile of B, shifted in from the top once the grid is
+    /// done with the previous tile. Rows of A hop right, and partial sums flow down the columns,
+    /// starting from the partial of the previous tile of K.
+    fn weight_stationary(
+        &self,
+        a: &[Vec<Timed<InputT>>],
+        b_t: &[Vec<Timed<InputT>>],
+        out: &mut [Vec<Timed<OutputT>>],
+    ) {
+        let ii = self.timing.dot_ii;
+        let mut fired = vec![vec![0; self.grid.cols]; self.grid.rows];
+        for n0 in (0..self.shape.n).step_by(self.grid.cols) {
+            let cols = self.grid.cols.min(self.shape.n - n0);
+            for k0 in (0..self.shape.k).step_by(self.grid.rows) {
+                let depth = self.grid.rows.min(self.shape.k - k0);
+                let load_start = (0..depth)
+                    .flat_map(|i| (0..cols).map(move |j| (i, j)))
+                    .map(|(i, j)| fired[i][j].max(b_t[n0 + j][k0 + i].1))
+                    .max()
+                    .unwrap();
+                let loaded = load_start + depth as u64 * ii;
+                for (a_row, out_row) in a.iter().zip(out.iter_mut()) {
+                    let mut psums = out_row[n0..n0 + cols].to_vec();
+                    for i in 0..depth {
+                        for (j, (psum, psum_arrival)) in psums.iter_mut().enumerate() {
+                            let (a_elem, a_arrival) = &a_row[k0 + i];
+                            let a_in = if j == 0 {
+                                *a_arrival
+                            } else {
+                                fired[i][j - 1] + ii
+                            };
+                            let psum_in = if i == 0 {
+                                *psum_arrival
+                            } else {
+                                fired[i - 1][j] + ii
+                            };
+                            fired[i][j] = a_in.max(psum_in).max(loaded).max(fired[i][j] + ii);
+                            *psum =
+                                (self.mac)(a_elem.clone(), b_t[n0 + j][k0 + i].0.clone(), *psum);
+                        }
+                    }
+                    for (j, (psum, _)) in psums.into_iter().enumerate() {
+                        out_row[n0 + j] = (psum, fired[depth - 1][j] + self.timing.dot_latency);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Each PE holds one element of a PxQ tile of A^T, shifted in from the top once the grid is
+    /// done with the previous tile. Columns of B hop right, and partial sums flow down the
+    /// columns, starting from the partial of the previous tile of K.
+    fn input_stationary(
+        &self,
+        a: &[Vec<Timed<InputT>>],
+        b_t: &[Vec<Timed<InputT>>],
+        out: &mut [Vec<Timed<OutputT>>],
+    ) {
+        let ii = self.timing.dot_ii;
+        let cols = a.len();
+        let mut fired = vec![vec![0; cols]; self.grid.rows];
+        for k0 in (0..self.shape.k).step_by(self.grid.rows) {
+            let depth = self.grid.rows.min(self.shape.k - k0);
+            let load_start = (0..depth)
+                .flat_map(|i| (0..cols).map(move |j| (i, j)))
+                .map(|(i, j)| fired[i][j].max(a[j][k0 + i].1))
+                .max()
+                .unwrap();
+            let loaded = load_start + depth as u64 * ii;
+            for (n, b_col) in b_t.iter().enumerate() {
+                let mut psums = out.iter().map(|row| row[n]).collect::<Vec<_>>();
+                for i in 0..depth {
+                    for (j, (psum, psum_arrival)) in psums.iter_mut().enumerate() {
+                        let (b_elem, b_arrival) = &b_col[k0 + i];
+                        let b_in = if j == 0 {
+                            *b_arrival
+                        } else {
+                            fired[i][j - 1] + ii
+                        };
+                        let psum_in = if i == 0 {
+                            *psum_arrival
+                        } else {
+                            fired[i - 1][j] + ii
+                        };
+                        fired[i][j] = b_in.max(psum_in).max(loaded).max(fired[i][j] + ii);
+                        *psum = (self.mac)(a[j][k0 + i].0.clone(), b_elem.clone(), *psum);
+                    }
+                }
+                for (j, (psum, _)) in psums.into_iter().enumerate() {
+                    out[j][n] = (psum, fired[depth - 1][j] + self.timing.dot_latency);
+                }
+            }
+        }
+    }
+
+    fn systolic_matmul(&self) {
+        let rows_per_pass = self.rows_per_pass();
+        loop {
+            let mut m0 = 0;
+            while m0 < self.shape.m {
+                let rows = rows_per_pass.min(self.shape.m - m0);
+                self.time.incr_cycles(self.timing.reset_time);
+
+                let mut a_block = Vec::with_capacity(rows);
+                for r in 0..rows {
+                    let mut a_row = Vec::with_capacity(self.shape.k);
+                    for k in 0..self.shape.k {
+                        match self.left.dequeue(&self.time) {
+                            Ok(ChannelElement { time: _, data }) => {
+                                a_row.push((data, self.time.tick().time()))
+                            }
+                            Err(_) if m0 == 0 && r == 0 && k == 0 => return,
+                            Err(_) => {
+                                panic!("Unexpected termination of left stream in systolic matmul ID: {:?} at time {:?} on iteration {}, {k}", self.id, self.time.tick(), m0 + r);
+                            }
+                        }
+                    }
+                    a_block.push(a_row);
+                }
+
+                // B^T arrives once per row of A, but the grid only needs one copy of it per pass.
+                let mut b_transposed = vec![Vec::with_capacity(self.shape.k); self.shape.n];
+                for r in 0..rows {
+                    for (n, b_row) in b_transposed.iter_mut().enumerate() {
+                        for k in 0..self.shape.k {
+                            match self.right.dequeue(&self.time) {
+                                Ok(ChannelElement { time: _, data }) if r == 0 => {
+                                    b_row.push((data, self.time.tick().time()))
+                                }
+                                Ok(_) => {}
+                                Err(_) => {
+                                    panic!("Unexpected termination of right stream in systolic matmul ID: {:?} at time {:?} on iteration {}, {n}, {k}", self.id, self.time.tick(), m0 + r);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let mut results = vec![vec![(OutputT::zero(), 0); self.shape.n]; rows];
+                match self.dataflow {
+                    SystolicDataflow::OutputStationary => {
+                        self.output_stationary(&a_block, &b_transposed, &mut results)
+                    }
+                    SystolicDataflow::WeightStationary => {
+                        self.weight_stationary(&a_block, &b_transposed, &mut results)
+                    }
+                    SystolicDataflow::InputStationary => {
+                        self.input_stationary(&a_block, &b_transposed, &mut results)
+                    }
+                }
+
+                for (data, exit) in results.into_iter().flatten() {
+                    self.time
+                        .incr_cycles(exit.saturating_sub(self.time.tick().time()));
+                    self.output
+                        .enqueue(
+                            &self.time,
+                            ChannelElement {
+                                time: self.time.tick(),
+                                data,
+                            },
+                        )
+                        .unwrap_or_else(|_| {
+                            panic!(
+                                "Unexpected termination of output channel on SystolicMatmul {:?}",
+                                self.id
+                            )
+                        });
+                }
+                m0 += rows;
+            }
+        }
+    }
+}
+
+impl<InputT, OutputT, MacT> Context for SystolicMatmul<InputT, OutputT, MacT>
+where
+    InputT: DAMType,
+    OutputT: DAMType + num::Float,
+    MacT: Fn(InputT, InputT, OutputT) -> OutputT + Sync + Send,
+{
+    fn run(&mut self) {
+        self.systolic_matmul()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{ApproxCheckerContext, GeneratorContext},
+    };
+    use ndarray::ArcArray;
+
+    use super::*;
+
+    const CHAN_DEPTH: usize = 8;
+
+    fn run_test(dataflow: SystolicDataflow, grid: SystolicGrid, shape: ShapeInfo) {
+        const OUTER_ITERATIONS: usize = 2;
+        let a_matrices = (0..OUTER_ITERATIONS)
+            .map(|_| ArcArray::from_shape_simple_fn([shape.m, shape.k], fastrand::f32))
+            .collect::<Vec<_>>();
+        let b_matrices = (0..OUTER_ITERATIONS)
+            .map(|_| ArcArray::from_shape_simple_fn([shape.k, shape.n], fastrand::f32))
+            .collect::<Vec<_>>();
+
+        let mut builder = ProgramBuilder::default();
+        let (a_snd, a_recv) = builder.bounded(CHAN_DEPTH);
+        let (b_snd, b_recv) = builder.bounded(CHAN_DEPTH);
+        let (c_snd, c_recv) = builder.bounded(CHAN_DEPTH);
+
+        // Same streams as the buffered Matmul
+        builder.add_child(GeneratorContext::new(
+            || a_matrices.iter().flat_map(|mat| mat.into_iter()).copied(),
+            a_snd,
+        ));
+        builder.add_child(GeneratorContext::new(
+            || {
+                b_matrices.iter().flat_map(|mat_b| {
+                    (0..shape.m).flat_map(move |_| {
+                        mat_b.t().iter().copied().collect::<Vec<_>>().into_iter()
+                    })
+                })
+            },
+            b_snd,
+        ));
+        builder.add_child(SystolicMatmul::new(
+            MatmulTiming {
+                dot_latency: 1,
+                dot_ii: 1,
+                reset_time: 0,
+                vector_width: 1,
+                reduction_latency: 0,
+                batch_reset_time: 0,
+            },
+            dataflow,
+            grid,
+            shape,
+            a_recv,
+            b_recv,
+            c_snd,
+            |a, b, c| a * b + c,
+        ));
+
+        builder.add_child(ApproxCheckerContext::new(
+            || {
+                a_matrices
+                    .iter()
+                    .zip(b_matrices.iter())
+                    .flat_map(|(a, b)| a.dot(b).into_iter())
+            },
+            c_recv,
+            |a, b| (a - b).abs() < 0.001,
+        ));
+
+        let executed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+        dbg!(executed.elapsed_cycles());
+    }
+
+    const GRID: SystolicGrid = SystolicGrid { rows: 4, cols: 8 };
+    // M is not a multiple of the grid, so the last pass is partial.
+    const SHAPE: ShapeInfo = ShapeInfo {
+        m: 30,
+        n: 32,
+        k: 16,
+        batch: 1,
+        broadcast: BatchBroadcast::None,
+    };
+
+    #[test]
+    fn run_output_stationary() {
+        run_test(SystolicDataflow::OutputStationary, GRID, SHAPE);
+    }
+
+    #[test]
+    fn run_weight_stationary() {
+        run_test(SystolicDataflow::WeightStationary, GRID, SHAPE);
+    }
+
+    #[test]
+    fn run_input_stationary() {
+        run_test(SystolicDataflow::InputStationary, GRID, SHAPE);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_batches() {
+        let mut builder = ProgramBuilder::default();
+        let (_, a_recv) = builder.bounded::<f32>(CHAN_DEPTH);
+        let (_, b_recv) = builder.bounded::<f32>(CHAN_DEPTH);
+        let (c_snd, _) = builder.bounded::<f32>(CHAN_DEPTH);
+        SystolicMatmul::new(
+            MatmulTiming {
+                dot_latency: 1,
+                dot_ii: 1,
+                reset_time: 0,
+                vector_width: 1,
+                reduction_latency: 0,
+                batch_reset_time: 0,
+            },
+            SystolicDataflow::OutputStationary,
+            GRID,
+            ShapeInfo { batch: 2, ..SHAPE },
+            a_recv,
+            b_recv,
+            c_snd,
+            |a, b, c| a * b + c,
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use dam::context_tools::*;
+use ndarray::{linalg::Dot, Array2, Ix2, LinalgScalar};
+
+use super::{BatchBroadcast, ShapeInfo, Tensor};
+
+#[derive(Debug, Copy, Clone)]
+pub struct TileMatmulTiming {
+    /// Number of scalar MACs the tile engine completes per cycle
+    pub macs_per_cycle: usize,
+    pub latency: u64,
+    pub reset_time: u64,
+}
+
+impl TileMatmulTiming {
+    /// Cycles to multiply an [m, k] tile with a [k, n] tile
+    fn tile_cycles(&self, m: usize, n: usize, k: usize) -> u64 {
+        (m * n * k).div_ceil(self.macs_per_cycle) as u64
+    }
+}
+
+/// Computes A: [M, K] x B[K, N] = C [M, N] one tile at a time.
+/// `tiles` is the shape measured in tiles, while the tile dimensions come from the tensors themselves,
+/// so edge tiles may be smaller. Tiles follow the order of [super::MatmulBehavior::Buffered]:
+/// the K tiles of a row of A are read once, and the tiles of B are read column by column once per row of A.
+#[context_macro]
+pub struct TileMatmul<A: DAMType> {
+    timing: TileMatmulTiming,
+    tiles: ShapeInfo,
+    left: Receiver<Tensor<A, Ix2>>,
+    right: Receiver<Tensor<A, Ix2>>,
+    output: Sender<Tensor<A, Ix2>>,
+}
+
+impl<A: DAMType + LinalgScalar> TileMatmul<A> {
+    pub fn new(
+        timing: TileMatmulTiming,
+        tiles: ShapeInfo,
+        left: Receiver<Tensor<A, Ix2>>,
+        right: Receiver<Tensor<A, Ix2>>,
+        output: Sender<Tensor<A, Ix2>>,
+    ) -> Self {
+        assert!(timing.macs_per_cycle > 0);
+        assert_eq!(tiles.batch, 1, "TileMatmul doesn't support batches");
+        assert_eq!(
+            tiles.broadcast,
+            BatchBroadcast::None,
+            "TileMatmul doesn't replay broadcast operands"
+        );
+        let output = Self {
+            timing,
+            tiles,
+            left,
+            right,
+            output,
+            context_info: Default::default(),
+        };
+        output.left.attach_receiver(&output);
+        output.right.attach_receiver(&output);
+        output.output.attach_sender(&output);
+        output
+    }
+}
+
+impl<A: DAMType + LinalgScalar> Context for TileMatmul<A> {
+    fn run(&mut self) {
+        let mut left_buffer = Vec::with_capacity(self.tiles.k);
+        loop {
+            // Loop over M
+            for m in 0..self.tiles.m {
+                self.time.incr_cycles(self.timing.reset_time);
+                for n in 0..self.tiles.n {
+                    let should_populate_buffer = n == 0;
+                    let mut accum: Option<Array2<A>> = None;
+                    for k in 0..self.tiles.k {
+                        if should_populate_buffer {
+                            match self.left.dequeue(&self.time) {
+                                Ok(ChannelElement { time: _, data }) => left_buffer.push(data),
+                                Err(_) if m == 0 && n == 0 && k == 0 => return,
+                                Err(_) => {
+                                    panic!("Unexpected termination of left stream in tile matmul ID: {:?} at time {:?} on iteration {m}, {n}, {k}", self.id, self.time.tick());
+                                }
+                            }
+                        }
+                        let right_tile = match self.right.dequeue(&self.time) {
+                            Ok(ChannelElement { time: _, data }) => data,
+                            Err(_) => {
+                                panic!("Unexpected termination of right stream in tile matmul ID: {:?} at time {:?} on iteration {m}, {n}, {k}", self.id, self.time.tick());
+                            }
+                        };
+                        let left_tile: &Tensor<A, Ix2> = &left_buffer[k];
+                        let (rows, inner) = left_tile.dim();
+                        assert_eq!(
+                            inner,
+                            right_tile.nrows(),
+                            "Mismatched tile shapes in tile matmul ID: {:?}",
+                            self.id
+                        );
+                        let product = left_tile.dot(&right_tile);
+                        accum = Some(match accum {
+                            Some(partial) => partial + product,
+                            None => product,
+                        });
+                        self.time.incr_cycles(self.timing.tile_cycles(
+                            rows,
+                            right_tile.ncols(),
+                            inner,
+                        ));
+                    }
+                    self.output
+                        .enqueue(
+                            &self.time,
+                            ChannelElement {
+                                time: self.time.tick() + self.timing.latency,
+                                data: Tensor(accum.unwrap().into_shared()),
+                            },
+                        )
+                        .unwrap_or_else(|_| {
+                            panic!(
+                                "Unexpected termination of output channel on TileMatmul {:?}",
+                                self.id
+                            )
+                        });
+                }
+                left_buffer.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{ApproxCheckerContext, GeneratorContext},
+    };
+    use ndarray::{s, ArcArray, ArrayView2};
+
+    use super::*;
+
+    const CHAN_DEPTH: usize = 4;
+
+    fn tile(
+        mat: ArrayView2<f32>,
+        row: usize,
+        col: usize,
+        rows: usize,
+        cols: usize,
+    ) -> Tensor<f32, Ix2> {
+        let row_end = (row * rows + rows).min(mat.nrows());
+        let col_end = (col * cols + cols).min(mat.ncols());
+        Tensor(
+            mat.slice(s![row * rows..row_end, col * cols..col_end])
+                .to_owned()
+                .into_shared(),
+        )
+    }
+
+    #[test]
+    fn run_tile_matmul() {
+        const M: usize = 64;
+        const N: usize = 40;
+        const K: usize = 24;
+        // N is not a multiple of the tile width, so the last column of tiles is narrower.
+        const TILE: usize = 16;
+        let tiles = ShapeInfo {
+            m: M.div_ceil(TILE),
+            n: N.div_ceil(TILE),
+            k: K.div_ceil(TILE),
+            batch: 1,
+            broadcast: BatchBroadcast::None,
+        };
+        let a = ArcArray::from_shape_simple_fn([M, K], fastrand::f32);
+        let b = ArcArray::from_shape_simple_fn([K, N], fastrand::f32);
+        let c = a.dot(&b);
+
+        let mut builder = ProgramBuilder::default();
+        let (a_snd, a_recv) = builder.bounded(CHAN_DEPTH);
+        let (b_snd, b_recv) = builder.bounded(CHAN_DEPTH);
+        let (c_snd, c_recv) = builder.bounded(CHAN_DEPTH);
+
+        builder.add_child(GeneratorContext::new(
+            || {
+                (0..tiles.m)
+                    .flat_map(move |m| (0..tiles.k).map(move |k| (m, k)))
+                    .map(|(m, k)| tile(a.view(), m, k, TILE, TILE))
+            },
+            a_snd,
+        ));
+        builder.add_child(GeneratorContext::new(
+            || {
+                (0..tiles.m)
+                    .flat_map(move |_| 0..tiles.n)
+                    .flat_map(move |n| (0..tiles.k).map(move |k| (k, n)))
+                    .map(|(k, n)| tile(b.view(), k, n, TILE, TILE))
+            },
+            b_snd,
+        ));
+        builder.add_child(TileMatmul::new(
+            TileMatmulTiming {
+                macs_per_cycle: 256,
+                latency: 4,
+                reset_time: 0,
+            },
+            tiles,
+            a_recv,
+            b_recv,
+            c_snd,
+        ));
+        builder.add_child(ApproxCheckerContext::new(
+            || {
+                (0..tiles.m)
+                    .flat_map(move |m| (0..tiles.n).map(move |n| (m, n)))
+                    .map(|(m, n)| tile(c.view(), m, n, TILE, TILE))
+            },
+            c_recv,
+            |a, b| a.dim() == b.dim() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 0.001),
+        ));
+
+        let executed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+        dbg!(executed.elapsed_cycles());
+    }
+}
@@ -0,0 +1,361 @@
+use dam::context_tools::*;
+use ndarray::{s, Array2, Ix2};
+
+use super::Tensor;
+
+/// An [rows, cols] matrix cut into [tile_rows, tile_cols] tiles.
+/// Tiles are ordered row-major, and the last row/column of tiles may be smaller.
+#[derive(Debug, Clone, Copy)]
+pub struct TileLayout {
+    pub rows: usize,
+    pub cols: usize,
+    pub tile_rows: usize,
+    pub tile_cols: usize,
+}
+
+impl TileLayout {
+    /// Number of elements in a full row of tiles
+    pub fn stripe_size(&self) -> usize {
+        self.tile_rows * self.cols
+    }
+
+    fn stripes(&self) -> usize {
+        self.rows.div_ceil(self.tile_rows)
+    }
+
+    fn tiles_per_stripe(&self) -> usize {
+        self.cols.div_ceil(self.tile_cols)
+    }
+
+    fn stripe_rows(&self, stripe: usize) -> usize {
+        self.tile_rows.min(self.rows - stripe * self.tile_rows)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileTimings {
+    /// Cycles per scalar on the scalar side
+    pub element_ii: u64,
+    /// Cycles per tile on the tile side
+    pub tile_ii: u64,
+    pub latency: u64,
+}
+
+/// Tracks when the output side of a tiling buffer is free.
+/// With room for two stripes, a stripe drains while the next one fills; otherwise filling stalls.
+struct StripeBuffer {
+    double_buffered: bool,
+    drain_free: u64,
+}
+
+impl StripeBuffer {
+    fn new(layout: &TileLayout, capacity: usize) -> Self {
+        assert!(
+            capacity >= layout.stripe_size(),
+            "Tiling buffer of {capacity} elements can't hold a stripe of {} elements",
+            layout.stripe_size()
+        );
+        Self {
+            double_buffered: capacity >= 2 * layout.stripe_size(),
+            drain_free: 0,
+        }
+    }
+
+    /// Cycles from now until the stripe can start draining.
+    fn drain_offset(&self, now: u64) -> u64 {
+        if self.double_buffered {
+            self.drain_free.saturating_sub(now)
+        } else {
+            0
+        }
+    }
+
+    /// Records a drain that starts `offset` cycles from now and takes `cycles`.
+    /// Returns how long the filling side has to stall for it.
+    fn drain(&mut self, now: u64, offset: u64, cycles: u64) -> u64 {
+        self.drain_free = now + offset + cycles;
+        if self.double_buffered {
+            0
+        } else {
+            cycles
+        }
+    }
+}
+
+/// Assembles a row-major scalar stream into a stream of tiles.
+#[context_macro]
+pub struct Tile<A: DAMType> {
+    layout: TileLayout,
+    capacity: usize,
+    input: Receiver<A>,
+    output: Sender<Tensor<A, Ix2>>,
+    timings: TileTimings,
+}
+
+impl<A: DAMType> Tile<A> {
+    /// `capacity` is the size of the on-chip buffer in elements, and has to hold at least one row of tiles.
+    pub fn new(
+        layout: TileLayout,
+        capacity: usize,
+        input: Receiver<A>,
+        output: Sender<Tensor<A, Ix2>>,
+        timings: TileTimings,
+    ) -> Self {
+        let s = Self {
+            layout,
+            capacity,
+            input,
+            output,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<A: DAMType> Context for Tile<A> {
+    fn run(&mut self) {
+        let mut buffer = StripeBuffer::new(&self.layout, self.capacity);
+        loop {
+            for stripe in 0..self.layout.stripes() {
+                let stripe_rows = self.layout.stripe_rows(stripe);
+                let mut elements = Vec::with_capacity(stripe_rows * self.layout.cols);
+                for i in 0..(stripe_rows * self.layout.cols) {
+                    match self.input.dequeue(&self.time) {
+                        Ok(ChannelElement { time: _, data }) => elements.push(data),
+                        Err(_) if stripe == 0 && i == 0 => return,
+                        Err(_) => panic!(
+                            "Premature End of Receiver {:?} on Tile {:?}",
+                            self.input.id(),
+                            self.id
+                        ),
+                    }
+                    self.time.incr_cycles(self.timings.element_ii);
+                }
+                let stripe_data =
+                    Array2::from_shape_vec((stripe_rows, self.layout.cols), elements).unwrap();
+
+                let now = self.time.tick().time();
+                let offset = buffer.drain_offset(now);
+                for (i, col) in (0..self.layout.cols)
+                    .step_by(self.layout.tile_cols)
+                    .enumerate()
+                {
+                    let col_end = (col + self.layout.tile_cols).min(self.layout.cols);
+                    let tile = stripe_data.slice(s![.., col..col_end]).to_owned();
+                    self.output
+                        .enqueue(
+                            &self.time,
+                            ChannelElement {
+                                time: self.time.tick()
+                                    + offset
+                                    + self.timings.latency
+                                    + i as u64 * self.timings.tile_ii,
+                                data: Tensor(tile.into_shared()),
+                            },
+                        )
+                        .unwrap_or_else(|_| {
+                            panic!("Premature End of Sender on Tile {:?}", self.id)
+                        });
+                }
+                let drain_cycles = self.layout.tiles_per_stripe() as u64 * self.timings.tile_ii;
+                self.time
+                    .incr_cycles(buffer.drain(now, offset, drain_cycles));
+            }
+        }
+    }
+}
+
+/// Flattens a stream of tiles back into a row-major scalar stream.
+#[context_macro]
+pub struct Untile<A: DAMType> {
+    layout: TileLayout,
+    capacity: usize,
+    input: Receiver<Tensor<A, Ix2>>,
+    output: Sender<A>,
+    timings: TileTimings,
+}
+
+impl<A: DAMType> Untile<A> {
+    /// `capacity` is the size of the on-chip buffer in elements, and has to hold at least one row of tiles.
+    pub fn new(
+        layout: TileLayout,
+        capacity: usize,
+        input: Receiver<Tensor<A, Ix2>>,
+        output: Sender<A>,
+        timings: TileTimings,
+    ) -> Self {
+        let s = Self {
+            layout,
+            capacity,
+            input,
+            output,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<A: DAMType> Context for Untile<A> {
+    fn run(&mut self) {
+        let mut buffer = StripeBuffer::new(&self.layout, self.capacity);
+        loop {
+            for stripe in 0..self.layout.stripes() {
+                let mut tiles = Vec::with_capacity(self.layout.tiles_per_stripe());
+                for i in 0..self.layout.tiles_per_stripe() {
+                    match self.input.dequeue(&self.time) {
+                        Ok(ChannelElement { time: _, data }) => tiles.push(data),
+                        Err(_) if stripe == 0 && i == 0 => return,
+                        Err(_) => panic!(
+                            "Premature End of Receiver {:?} on Untile {:?}",
+                            self.input.id(),
+                            self.id
+                        ),
+                    }
+                    self.time.incr_cycles(self.timings.tile_ii);
+                }
+
+                let now = self.time.tick().time();
+                let offset = buffer.drain_offset(now);
+                let mut emitted = 0;
+                for row in 0..self.layout.stripe_rows(stripe) {
+                    for tile in tiles.iter() {
+                        for value in tile.row(row) {
+                            self.output
+                                .enqueue(
+                                    &self.time,
+                                    ChannelElement {
+                                        time: self.time.tick()
+                                            + offset
+                                            + self.timings.latency
+                                            + emitted * self.timings.element_ii,
+                                        data: value.clone(),
+                                    },
+                                )
+                                .unwrap_or_else(|_| {
+                                    panic!("Premature End of Sender on Untile {:?}", self.id)
+                                });
+                            emitted += 1;
+                        }
+                    }
+                }
+                self.time
+                    .incr_cycles(buffer.drain(now, offset, emitted * self.timings.element_ii));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use ndarray::Ix2;
+
+    use crate::templates::{BroadcastSender, Map, MapTimings, Tensor, Vector};
+
+    use super::{Tile, TileLayout, TileTimings, Untile};
+
+    #[test]
+    fn tile_order() {
+        let layout = TileLayout {
+            rows: 5,
+            cols: 7,
+            tile_rows: 2,
+            tile_cols: 3,
+        };
+        let timings = TileTimings {
+            element_ii: 1,
+            tile_ii: 1,
+            latency: 1,
+        };
+        // Tiles go left to right within a stripe, then stripe by stripe, each of them row-major.
+        let mut gold = vec![];
+        for row0 in (0..layout.rows).step_by(layout.tile_rows) {
+            for col0 in (0..layout.cols).step_by(layout.tile_cols) {
+                let mut tile = vec![];
+                for row in row0..(row0 + layout.tile_rows).min(layout.rows) {
+                    for col in col0..(col0 + layout.tile_cols).min(layout.cols) {
+                        tile.push((row * layout.cols + col) as u64);
+                    }
+                }
+                gold.push(Vector::from(tile));
+            }
+        }
+        assert_eq!(gold.len(), 9);
+
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(16);
+        let (tile_snd, tile_rcv) = builder.bounded(4);
+        let (out_snd, out_rcv) = builder.bounded(4);
+        builder.add_child(GeneratorContext::new(
+            || 0..(layout.rows * layout.cols) as u64,
+            in_snd,
+        ));
+        builder.add_child(Tile::new(
+            layout,
+            layout.stripe_size(),
+            in_rcv,
+            tile_snd,
+            timings,
+        ));
+        builder.add_child(Map::new(
+            vec![tile_rcv],
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            |tile: &[Tensor<u64, Ix2>]| Vector::from(tile[0].iter().copied().collect::<Vec<_>>()),
+            MapTimings {
+                initiation_interval: 1,
+                latency: 1,
+            },
+        ));
+        builder.add_child(CheckerContext::new(|| gold.into_iter(), out_rcv));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+
+    #[test]
+    fn tile_roundtrip() {
+        // Neither dimension is a multiple of the tile shape.
+        let layout = TileLayout {
+            rows: 20,
+            cols: 13,
+            tile_rows: 8,
+            tile_cols: 4,
+        };
+        let timings = TileTimings {
+            element_ii: 1,
+            tile_ii: 2,
+            latency: 3,
+        };
+        for capacity in [layout.stripe_size(), 2 * layout.stripe_size()] {
+            let values: Vec<u64> = (0..(2 * layout.rows * layout.cols) as u64).collect();
+            let mut builder = ProgramBuilder::default();
+            let (in_snd, in_rcv) = builder.bounded(16);
+            let (tile_snd, tile_rcv) = builder.bounded(4);
+            let (out_snd, out_rcv) = builder.bounded(16);
+            let inputs = values.clone();
+            builder.add_child(GeneratorContext::new(|| inputs.into_iter(), in_snd));
+            builder.add_child(Tile::new(layout, capacity, in_rcv, tile_snd, timings));
+            builder.add_child(Untile::new(layout, capacity, tile_rcv, out_snd, timings));
+            builder.add_child(CheckerContext::new(|| values.into_iter(), out_rcv));
+            let elapsed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default())
+                .elapsed_cycles();
+            dbg!(capacity, elapsed);
+        }
+    }
+}
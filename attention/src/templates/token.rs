@@ -0,0 +1,508 @@
+use dam::context_tools::*;
+
+use super::{BroadcastSender, MapTimings, ReduceTimings, RepeatTimings, ScanTimings};
+
+/// A stream element that carries either data or a stop token, so that the structure of a stream
+/// travels with it instead of being configured up front.
+/// Stop(n) closes the innermost n + 1 levels: the rows [[1, 2], [], [3]] are
+/// `1 2 S0 S0 3 S1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token<T> {
+    Val(T),
+    Stop(usize),
+}
+
+impl<T> Default for Token<T> {
+    fn default() -> Self {
+        Token::Stop(0)
+    }
+}
+
+impl<T: DAMType> DAMType for Token<T> {
+    fn dam_size(&self) -> usize {
+        1 + match self {
+            Token::Val(value) => value.dam_size(),
+            Token::Stop(level) => level.dam_size(),
+        }
+    }
+}
+
+fn send<T: DAMType>(
+    output: &BroadcastSender<T>,
+    time: &dam::structures::TimeManager,
+    latency: u64,
+    data: T,
+) {
+    output
+        .enqueue(
+            time,
+            ChannelElement {
+                time: time.tick() + latency,
+                data,
+            },
+        )
+        .unwrap();
+}
+
+/// Reduces every innermost fiber to a single value, removing one level of structure:
+/// `1 2 S0 S0 3 S1` becomes `3 0 3 S0`. An empty fiber reduces to OutT::default().
+#[context_macro]
+pub struct TokenReduce<InT: DAMType, OutT: DAMType, UpdateT> {
+    input: Receiver<Token<InT>>,
+    output: BroadcastSender<Token<OutT>>,
+    update_fn: UpdateT,
+    timings: ReduceTimings,
+}
+
+impl<InT: DAMType, OutT: DAMType, UpdateT> TokenReduce<InT, OutT, UpdateT>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<Token<InT>>,
+        output: BroadcastSender<Token<OutT>>,
+        update_fn: UpdateT,
+        timings: ReduceTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            output,
+            update_fn,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<InT: DAMType, OutT: DAMType, UpdateT> Context for TokenReduce<InT, OutT, UpdateT>
+where
+    UpdateT: Sync + Send + Fn(InT, Option<OutT>) -> OutT,
+{
+    fn run(&mut self) {
+        let mut accum: Option<OutT> = None;
+        let mut open = false;
+        loop {
+            let token = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => data,
+                Err(_) if !open => return,
+                Err(_) => panic!(
+                    "Premature End of Receiver {:?} on TokenReduce {:?}",
+                    self.input.id(),
+                    self.id
+                ),
+            };
+            match token {
+                Token::Val(value) => {
+                    accum = Some((self.update_fn)(value, accum.take()));
+                    open = true;
+                }
+                Token::Stop(level) => {
+                    let value = accum.take().unwrap_or_default();
+                    send(
+                        &self.output,
+                        &self.time,
+                        self.timings.latency,
+                        Token::Val(value),
+                    );
+                    if level > 0 {
+                        send(
+                            &self.output,
+                            &self.time,
+                            self.timings.latency,
+                            Token::Stop(level - 1),
+                        );
+                    }
+                    open = false;
+                    self.time.incr_cycles(self.timings.reset_time);
+                }
+            }
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+/// Scans every innermost fiber, restarting at each stop token and passing it through.
+#[context_macro]
+pub struct TokenScan<InT: DAMType, OutT: DAMType, UpdateT> {
+    input: Receiver<Token<InT>>,
+    output: BroadcastSender<Token<OutT>>,
+    update_fn: UpdateT,
+    timings: ScanTimings,
+}
+
+impl<InT: DAMType, OutT: DAMType, UpdateT> TokenScan<InT, OutT, UpdateT>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<Token<InT>>,
+        output: BroadcastSender<Token<OutT>>,
+        update_fn: UpdateT,
+        timings: ScanTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            output,
+            update_fn,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<InT: DAMType, OutT: DAMType, UpdateT> Context for TokenScan<InT, OutT, UpdateT>
+where
+    UpdateT: Sync + Send + Fn(InT, Option<&OutT>) -> OutT,
+{
+    fn run(&mut self) {
+        let mut accum: Option<OutT> = None;
+        loop {
+            let token = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => data,
+                Err(_) => return,
+            };
+            match token {
+                Token::Val(value) => {
+                    let new_val = (self.update_fn)(value, accum.as_ref());
+                    send(
+                        &self.output,
+                        &self.time,
+                        self.timings.latency,
+                        Token::Val(new_val.clone()),
+                    );
+                    accum = Some(new_val);
+                }
+                Token::Stop(level) => {
+                    send(
+                        &self.output,
+                        &self.time,
+                        self.timings.latency,
+                        Token::Stop(level),
+                    );
+                    accum = None;
+                    self.time.incr_cycles(self.timings.reset_time);
+                }
+            }
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+/// Repeats every value of the input over one fiber of `reference`, adding a level of structure:
+/// `3 0 3 S0` over the reference `a b S0 S0 c S1` becomes `3 3 S0 S0 3 S1`.
+/// The stops of the input are implied by those of the reference.
+#[context_macro]
+pub struct TokenRepeat<T: DAMType, RefT: DAMType> {
+    input: Receiver<Token<T>>,
+    reference: Receiver<Token<RefT>>,
+    output: BroadcastSender<Token<T>>,
+    timings: RepeatTimings,
+}
+
+impl<T: DAMType, RefT: DAMType> TokenRepeat<T, RefT>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<Token<T>>,
+        reference: Receiver<Token<RefT>>,
+        output: BroadcastSender<Token<T>>,
+        timings: RepeatTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            reference,
+            output,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.reference.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType, RefT: DAMType> Context for TokenRepeat<T, RefT> {
+    fn run(&mut self) {
+        // Level of the last stop taken from the reference
+        let mut last_stop = None;
+        loop {
+            let value = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement {
+                    time: _,
+                    data: Token::Val(value),
+                }) => value,
+                Ok(ChannelElement {
+                    time: _,
+                    data: Token::Stop(level),
+                }) => {
+                    assert_eq!(
+                        last_stop,
+                        Some(level + 1),
+                        "Stop tokens of the input and reference don't line up on TokenRepeat {:?}",
+                        self.id
+                    );
+                    continue;
+                }
+                Err(_) => return,
+            };
+            loop {
+                let token = match self.reference.dequeue(&self.time) {
+                    Ok(ChannelElement { time: _, data }) => data,
+                    Err(_) => panic!(
+                        "Premature End of Receiver {:?} on TokenRepeat {:?}",
+                        self.reference.id(),
+                        self.id
+                    ),
+                };
+                let (token, done) = match token {
+                    Token::Val(_) => (Token::Val(value.clone()), false),
+                    Token::Stop(level) => {
+                        last_stop = Some(level);
+                        (Token::Stop(level), true)
+                    }
+                };
+                send(&self.output, &self.time, self.timings.latency, token);
+                self.time.incr_cycles(self.timings.initiation_interval);
+                if done {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Applies a function to the values of several streams of the same structure, whose stop tokens
+/// are passed through.
+#[context_macro]
+pub struct TokenMap<InT: DAMType, OutT: DAMType, MapF> {
+    input: Vec<Receiver<Token<InT>>>,
+    output: BroadcastSender<Token<OutT>>,
+    mapf: MapF,
+    timings: MapTimings,
+}
+
+impl<InT: DAMType, OutT: DAMType, MapF> TokenMap<InT, OutT, MapF>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Vec<Receiver<Token<InT>>>,
+        output: BroadcastSender<Token<OutT>>,
+        mapf: MapF,
+        timings: MapTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            output,
+            mapf,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.iter().for_each(|chn| chn.attach_receiver(&s));
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<InT: DAMType, OutT: DAMType, MapF> Context for TokenMap<InT, OutT, MapF>
+where
+    MapF: Fn(&[InT]) -> OutT + Sync + Send,
+{
+    fn run(&mut self) {
+        loop {
+            let dequeued: Vec<_> = self
+                .input
+                .iter()
+                .map(|chn| chn.dequeue(&self.time))
+                .collect();
+            if dequeued.iter().any(|v| v.is_err()) {
+                return;
+            }
+            let tokens: Vec<_> = dequeued.into_iter().map(|v| v.unwrap().data).collect();
+            let output = match tokens[0] {
+                Token::Stop(level) => {
+                    assert!(
+                        tokens
+                            .iter()
+                            .all(|token| matches!(token, Token::Stop(l) if *l == level)),
+                        "Stop tokens don't line up on TokenMap {:?}: {tokens:?}",
+                        self.id
+                    );
+                    Token::Stop(level)
+                }
+                Token::Val(_) => {
+                    let values: Vec<_> = tokens
+                        .into_iter()
+                        .map(|token| match token {
+                            Token::Val(value) => value,
+                            Token::Stop(_) => {
+                                panic!("Stop tokens don't line up on TokenMap {:?}", self.id)
+                            }
+                        })
+                        .collect();
+                    Token::Val((self.mapf)(&values))
+                }
+            };
+            send(&self.output, &self.time, self.timings.latency, output);
+            self.time.incr_cycles(self.timings.initiation_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::{
+        BroadcastSender, MapTimings, ReduceTimings, RepeatTimings, ScanTimings,
+    };
+
+    use super::{Token, TokenMap, TokenReduce, TokenRepeat, TokenScan};
+
+    /// Flattens rows into a token stream, closing the last one with S1.
+    fn tokens(rows: &[Vec<u64>]) -> Vec<Token<u64>> {
+        let mut tokens = vec![];
+        for (i, row) in rows.iter().enumerate() {
+            tokens.extend(row.iter().map(|x| Token::Val(*x)));
+            tokens.push(Token::Stop(if i + 1 == rows.len() { 1 } else { 0 }));
+        }
+        tokens
+    }
+
+    #[test]
+    fn token_stream_test() {
+        // Ragged rows, including an empty one, without any shape configuration.
+        let rows: Vec<Vec<u64>> = vec![
+            vec![1, 2, 3],
+            vec![],
+            vec![4, 5],
+            vec![6],
+            vec![7, 8, 9, 10],
+        ];
+        let sums: Vec<u64> = rows.iter().map(|row| row.iter().sum()).collect();
+
+        let mut builder = ProgramBuilder::default();
+        let (in_snd, in_rcv) = builder.bounded(8);
+        let (to_reduce_snd, to_reduce_rcv) = builder.bounded(8);
+        let (to_repeat_snd, to_repeat_rcv) = builder.bounded(8);
+        let (to_map_snd, to_map_rcv) = builder.bounded(32);
+        let (to_scan_snd, to_scan_rcv) = builder.bounded(8);
+        builder.add_child(GeneratorContext::new(|| tokens(&rows).into_iter(), in_snd));
+        builder.add_child(TokenMap::new(
+            vec![in_rcv],
+            BroadcastSender {
+                targets: vec![to_reduce_snd, to_repeat_snd, to_map_snd, to_scan_snd],
+            },
+            |x: &[u64]| x[0],
+            MapTimings {
+                initiation_interval: 1,
+                latency: 1,
+            },
+        ));
+
+        // Row sums: one level less than the input.
+        let (sum_snd, sum_rcv) = builder.bounded(8);
+        let (sum_check_snd, sum_check_rcv) = builder.bounded(8);
+        builder.add_child(TokenReduce::new(
+            to_reduce_rcv,
+            BroadcastSender {
+                targets: vec![sum_snd, sum_check_snd],
+            },
+            |new, old: Option<u64>| old.unwrap_or(0) + new,
+            ReduceTimings {
+                initiation_interval: 1,
+                latency: 1,
+                reset_time: 0,
+            },
+        ));
+        builder.add_child(CheckerContext::new(
+            || sums.iter().map(|x| Token::Val(*x)).chain([Token::Stop(0)]),
+            sum_check_rcv,
+        ));
+
+        // Scale every element by its row sum, broadcasting the sums back over the rows.
+        let (repeated_snd, repeated_rcv) = builder.bounded(8);
+        builder.add_child(TokenRepeat::new(
+            sum_rcv,
+            to_repeat_rcv,
+            BroadcastSender {
+                targets: vec![repeated_snd],
+            },
+            RepeatTimings {
+                initiation_interval: 1,
+                latency: 1,
+            },
+        ));
+        let (scaled_snd, scaled_rcv) = builder.bounded(8);
+        builder.add_child(TokenMap::new(
+            vec![to_map_rcv, repeated_rcv],
+            BroadcastSender {
+                targets: vec![scaled_snd],
+            },
+            |x: &[u64]| 100 * x[0] / x[1],
+            MapTimings {
+                initiation_interval: 1,
+                latency: 1,
+            },
+        ));
+        builder.add_child(CheckerContext::new(
+            || {
+                let scaled: Vec<Vec<u64>> = rows
+                    .iter()
+                    .zip(&sums)
+                    .map(|(row, sum)| row.iter().map(|x| 100 * x / sum).collect())
+                    .collect();
+                tokens(&scaled).into_iter()
+            },
+            scaled_rcv,
+        ));
+
+        // Running sums within each row.
+        let (scan_snd, scan_rcv) = builder.bounded(8);
+        builder.add_child(TokenScan::new(
+            to_scan_rcv,
+            BroadcastSender {
+                targets: vec![scan_snd],
+            },
+            |new, old: Option<&u64>| old.unwrap_or(&0) + new,
+            ScanTimings {
+                initiation_interval: 1,
+                latency: 1,
+                reset_time: 0,
+            },
+        ));
+        builder.add_child(CheckerContext::new(
+            || {
+                let running: Vec<Vec<u64>> = rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .scan(0, |sum, x| {
+                                *sum += x;
+                                Some(*sum)
+                            })
+                            .collect()
+                    })
+                    .collect();
+                tokens(&running).into_iter()
+            },
+            scan_rcv,
+        ));
+
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+}
@@ -0,0 +1,162 @@
+use dam::context_tools::*;
+
+use super::{BroadcastSender, Pair};
+
+pub struct TopKTimings {
+    pub initiation_interval: u64,
+    /// Latency of a single compare-and-insert against one entry of the sorted buffer
+    pub comparator_latency: u64,
+    /// If set, the new element is compared against every buffer entry at once.
+    /// Otherwise the entries are walked one at a time, stalling the input.
+    pub parallel_compare: bool,
+    pub reset_time: u64,
+}
+
+/// Keeps the k best elements (according to `compare`) of every window of `reset_freq` inputs.
+/// At the end of each window the buffer is emitted best-first as (index within window, value).
+#[context_macro]
+pub struct TopK<T: DAMType, CmpF> {
+    k: usize,
+    reset_freq: usize,
+    input: Receiver<T>,
+    output: BroadcastSender<Pair<usize, T>>,
+    compare: CmpF,
+    timings: TopKTimings,
+}
+
+impl<T: DAMType, CmpF> TopK<T, CmpF>
+where
+    Self: Context,
+{
+    pub fn new(
+        k: usize,
+        reset_freq: usize,
+        input: Receiver<T>,
+        output: BroadcastSender<Pair<usize, T>>,
+        compare: CmpF,
+        timings: TopKTimings,
+    ) -> Self {
+        let s = Self {
+            k,
+            reset_freq,
+            input,
+            output,
+            compare,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType, CmpF> Context for TopK<T, CmpF>
+where
+    CmpF: Fn(&T, &T) -> bool + Sync + Send,
+{
+    fn run(&mut self) {
+        loop {
+            self.time.incr_cycles(self.timings.reset_time);
+            let mut buffer: Vec<Pair<usize, T>> = Vec::with_capacity(self.k + 1);
+            for iter in 0..self.reset_freq {
+                let input = match self.input.dequeue(&self.time) {
+                    Ok(ChannelElement { time: _, data }) => data,
+                    Err(_) if iter == 0 => return,
+                    Err(_) => panic!(
+                        "Premature End of Receiver {:?} on TopK {:?}",
+                        self.input.id(),
+                        self.id
+                    ),
+                };
+                // The first entry that the new element beats is where it gets inserted.
+                let position = buffer
+                    .iter()
+                    .position(|Pair(_, old)| (self.compare)(&input, old))
+                    .unwrap_or(buffer.len());
+                let cost = if self.timings.parallel_compare {
+                    self.timings.initiation_interval
+                } else {
+                    let comparisons = buffer.len().min(position + 1) as u64;
+                    self.timings
+                        .initiation_interval
+                        .max(comparisons * self.timings.comparator_latency)
+                };
+                if position < self.k {
+                    buffer.insert(position, Pair(iter, input));
+                    buffer.truncate(self.k);
+                }
+                self.time.incr_cycles(cost);
+            }
+
+            for entry in buffer {
+                self.output
+                    .enqueue(
+                        &self.time,
+                        ChannelElement {
+                            time: self.time.tick() + self.timings.comparator_latency,
+                            data: entry,
+                        },
+                    )
+                    .unwrap_or_else(|_| panic!("Premature End of Sender on TopK {:?}", self.id));
+                self.time.incr_cycles(self.timings.initiation_interval);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::{BroadcastSender, Pair};
+
+    use super::{TopK, TopKTimings};
+
+    #[test]
+    fn topk_test() {
+        const K: usize = 3;
+        let mut builder = ProgramBuilder::default();
+        let values: Vec<Vec<u64>> = (0..4)
+            .map(|base| (0..10).map(|x| (x * 7) % 10 + base * 10).collect())
+            .collect();
+        let inputs: Vec<_> = values.iter().flat_map(|x| x.iter().copied()).collect();
+        let (in_snd, in_rcv) = builder.bounded(16);
+        builder.add_child(GeneratorContext::new(|| inputs.into_iter(), in_snd));
+
+        let (out_snd, out_rcv) = builder.bounded(16);
+        builder.add_child(TopK::new(
+            K,
+            10,
+            in_rcv,
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            |new, old| new > old,
+            TopKTimings {
+                initiation_interval: 1,
+                comparator_latency: 1,
+                parallel_compare: false,
+                reset_time: 0,
+            },
+        ));
+        let gold: Vec<_> = values
+            .iter()
+            .flat_map(|vals| {
+                let mut indexed: Vec<_> = vals.iter().copied().enumerate().collect();
+                indexed.sort_by_key(|&(_, v)| std::cmp::Reverse(v));
+                indexed.into_iter().take(K).map(|(i, v)| Pair(i, v))
+            })
+            .collect();
+        builder.add_child(CheckerContext::new(|| gold.into_iter(), out_rcv));
+        let elapsed = builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default())
+            .elapsed_cycles();
+        dbg!(elapsed);
+    }
+}
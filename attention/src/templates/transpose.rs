@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+
+use dam::{channel::PeekResult, context_tools::*};
+
+#[derive(Debug, Clone, Copy)]
+pub struct TransposeTimings {
+    /// Cycles per element on the SRAM write port
+    pub write_ii: u64,
+    /// Cycles per element on the SRAM read port
+    pub read_ii: u64,
+    pub latency: u64,
+}
+
+/// Transposes a stream of [rows, cols] blocks: each block is written into SRAM row-major
+/// and read back out column-major once all of it has been written.
+/// The SRAM holds capacity / (rows * cols) blocks, so with room for two blocks one is read out
+/// while the next one is written. Whenever the input has nothing to write, every pending read is
+/// issued before waiting on it, so a producer that depends on this output never stalls the reads.
+#[context_macro]
+pub struct Transpose<A: DAMType> {
+    rows: usize,
+    cols: usize,
+    capacity: usize,
+    input: Receiver<A>,
+    output: Sender<A>,
+    timings: TransposeTimings,
+}
+
+/// Blocks that have been written, along with the cycle at which each one was completed.
+struct ReadPort<A> {
+    blocks: VecDeque<(u64, Vec<A>)>,
+    cursor: usize,
+    free: u64,
+}
+
+impl<A> ReadPort<A> {
+    /// Cycle at which the next read starts, if there is anything to read.
+    fn next_start(&self) -> Option<u64> {
+        self.blocks
+            .front()
+            .map(|(written, _)| self.free.max(*written))
+    }
+}
+
+impl<A: DAMType> Transpose<A> {
+    /// `capacity` is the size of the SRAM in elements, and has to hold at least one block.
+    pub fn new(
+        rows: usize,
+        cols: usize,
+        capacity: usize,
+        input: Receiver<A>,
+        output: Sender<A>,
+        timings: TransposeTimings,
+    ) -> Self {
+        assert!(
+            capacity >= rows * cols,
+            "Transpose buffer of {capacity} elements can't hold a {rows}x{cols} block"
+        );
+        let s = Self {
+            rows,
+            cols,
+            capacity,
+            input,
+            output,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+
+    /// Reads the next element of the oldest block. Returns true if that frees its buffer.
+    fn read_one(&self, port: &mut ReadPort<A>) -> bool {
+        let start = port.next_start().unwrap();
+        let (col, row) = (port.cursor / self.rows, port.cursor % self.rows);
+        let data = port.blocks[0].1[row * self.cols + col].clone();
+        let ready = start + self.timings.latency;
+        self.output
+            .enqueue(
+                &self.time,
+                ChannelElement {
+                    time: self.time.tick() + ready.saturating_sub(self.time.tick().time()),
+                    data,
+                },
+            )
+            .unwrap_or_else(|_| panic!("Premature End of Sender on Transpose {:?}", self.id));
+        port.free = start + self.timings.read_ii;
+        port.cursor += 1;
+        if port.cursor == self.rows * self.cols {
+            port.cursor = 0;
+            port.blocks.pop_front();
+            return true;
+        }
+        false
+    }
+
+    /// Whether the next input element has already arrived.
+    fn input_ready(&self) -> bool {
+        match self.input.peek() {
+            PeekResult::Something(ChannelElement { time, data: _ }) => time <= self.time.tick(),
+            PeekResult::Nothing(_) => false,
+            PeekResult::Closed => true,
+        }
+    }
+}
+
+impl<A: DAMType> Context for Transpose<A> {
+    fn run(&mut self) {
+        let block_size = self.rows * self.cols;
+        let buffers = self.capacity / block_size;
+        let mut port = ReadPort {
+            blocks: VecDeque::with_capacity(buffers),
+            cursor: 0,
+            free: 0,
+        };
+        let mut block = Vec::with_capacity(block_size);
+        loop {
+            if port.blocks.len() == buffers {
+                // Every buffer is full, so wait until the oldest one has been read out.
+                while !self.read_one(&mut port) {}
+                self.time
+                    .incr_cycles(port.free.saturating_sub(self.time.tick().time()));
+            }
+            // Keep the read port busy until the next write, or drain it if there is nothing to
+            // write yet.
+            let horizon = self.time.tick().time() + self.timings.write_ii;
+            while let Some(start) = port.next_start() {
+                if start >= horizon && self.input_ready() {
+                    break;
+                }
+                self.read_one(&mut port);
+            }
+
+            match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => block.push(data),
+                Err(_) if block.is_empty() => {
+                    while port.next_start().is_some() {
+                        self.read_one(&mut port);
+                    }
+                    return;
+                }
+                Err(_) => panic!(
+                    "Premature End of Receiver {:?} on Transpose {:?}",
+                    self.input.id(),
+                    self.id
+                ),
+            }
+            self.time.incr_cycles(self.timings.write_ii);
+            if block.len() == block_size {
+                port.blocks.push_back((
+                    self.time.tick().time(),
+                    std::mem::replace(&mut block, Vec::with_capacity(block_size)),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+    use ndarray::Array2;
+
+    use super::{Transpose, TransposeTimings};
+
+    #[test]
+    fn transpose_blocks() {
+        const ROWS: usize = 12;
+        const COLS: usize = 5;
+        let timings = TransposeTimings {
+            write_ii: 1,
+            read_ii: 1,
+            latency: 2,
+        };
+        let blocks: Vec<_> = (0..3)
+            .map(|_| Array2::from_shape_simple_fn([ROWS, COLS], || fastrand::u64(..)))
+            .collect();
+        let mut elapsed = vec![];
+        for capacity in [ROWS * COLS, 2 * ROWS * COLS] {
+            let mut builder = ProgramBuilder::default();
+            let (in_snd, in_rcv) = builder.bounded(8);
+            let (out_snd, out_rcv) = builder.bounded(8);
+            builder.add_child(GeneratorContext::new(
+                || blocks.iter().flat_map(|block| block.iter().copied()),
+                in_snd,
+            ));
+            builder.add_child(Transpose::new(
+                ROWS, COLS, capacity, in_rcv, out_snd, timings,
+            ));
+            builder.add_child(CheckerContext::new(
+                || {
+                    blocks
+                        .iter()
+                        .flat_map(|block| block.t().iter().copied().collect::<Vec<_>>())
+                },
+                out_rcv,
+            ));
+            let executed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+            elapsed.push(executed.elapsed_cycles().unwrap());
+        }
+        dbg!(&elapsed);
+        // Double buffering overlaps reading a block with writing the next one.
+        assert!(elapsed[1] < elapsed[0]);
+    }
+}
@@ -0,0 +1,200 @@
+use dam::context_tools::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TreeReduceTimings {
+    pub initiation_interval: u64,
+    /// Cycles per level of the adder tree
+    pub level_latency: u64,
+    /// Cycles for the accumulator to add one tree output to the running total
+    pub accumulate_latency: u64,
+    pub reset_time: u64,
+}
+
+/// Like [super::Reduce], but takes one element from each of its W inputs at a time and reduces
+/// them through a log2(W)-deep adder tree before adding them to the running total, e.g. for row
+/// sums and dot products. Element i of a window comes from input i % W.
+/// The accumulator only takes one tree output every `accumulate_latency` cycles, so when it falls
+/// behind the inputs stall.
+#[context_macro]
+pub struct TreeReduce<T: DAMType, ReduceF> {
+    reset_freq: usize,
+    inputs: Vec<Receiver<T>>,
+    output: Sender<T>,
+    reduce_fn: ReduceF,
+    timings: TreeReduceTimings,
+}
+
+impl<T: DAMType, ReduceF> TreeReduce<T, ReduceF>
+where
+    Self: Context,
+{
+    /// `reduce_fn` has to be associative.
+    pub fn new(
+        reset_freq: usize,
+        inputs: Vec<Receiver<T>>,
+        output: Sender<T>,
+        reduce_fn: ReduceF,
+        timings: TreeReduceTimings,
+    ) -> Self {
+        assert!(!inputs.is_empty());
+        let s = Self {
+            reset_freq,
+            inputs,
+            output,
+            reduce_fn,
+            timings,
+            context_info: Default::default(),
+        };
+        s.inputs.iter().for_each(|chn| chn.attach_receiver(&s));
+        s.output.attach_sender(&s);
+        s
+    }
+
+    /// Depth of the adder tree, log2(W) rounded up.
+    pub fn levels(&self) -> u64 {
+        self.inputs.len().next_power_of_two().trailing_zeros() as u64
+    }
+}
+
+impl<T: DAMType, ReduceF> TreeReduce<T, ReduceF>
+where
+    ReduceF: Fn(T, T) -> T,
+{
+    /// Reduces neighbouring pairs level by level, like the hardware does.
+    fn tree(&self, mut values: Vec<T>) -> T {
+        while values.len() > 1 {
+            let mut next = Vec::with_capacity(values.len().div_ceil(2));
+            let mut iter = values.into_iter();
+            while let Some(left) = iter.next() {
+                next.push(match iter.next() {
+                    Some(right) => (self.reduce_fn)(left, right),
+                    None => left,
+                });
+            }
+            values = next;
+        }
+        values.pop().unwrap()
+    }
+}
+
+impl<T: DAMType, ReduceF> Context for TreeReduce<T, ReduceF>
+where
+    ReduceF: Sync + Send + Fn(T, T) -> T,
+{
+    fn run(&mut self) {
+        let tree_latency = self.levels() * self.timings.level_latency;
+        // Cycle at which the accumulator can take the next tree output.
+        let mut accumulator_free: u64 = 0;
+        loop {
+            self.time.incr_cycles(self.timings.reset_time);
+            let mut accum: Option<T> = None;
+            let mut consumed = 0;
+            while consumed < self.reset_freq {
+                // Don't issue more than the accumulator can keep up with.
+                let issue = self.time.tick().time();
+                let stall = accumulator_free.saturating_sub(issue + tree_latency);
+                self.time.incr_cycles(stall);
+
+                let group = self.inputs.len().min(self.reset_freq - consumed);
+                let mut values = Vec::with_capacity(group);
+                for input in &self.inputs[..group] {
+                    match input.dequeue(&self.time) {
+                        Ok(ChannelElement { time: _, data }) => values.push(data),
+                        Err(_) if consumed == 0 && values.is_empty() => return,
+                        Err(_) => panic!(
+                            "Premature End of Receiver {:?} on TreeReduce {:?}",
+                            input.id(),
+                            self.id
+                        ),
+                    }
+                }
+                consumed += group;
+
+                let partial = self.tree(values);
+                accum = Some(match accum {
+                    Some(accum) => (self.reduce_fn)(accum, partial),
+                    None => partial,
+                });
+                let arrival = self.time.tick().time() + tree_latency;
+                accumulator_free = accumulator_free.max(arrival) + self.timings.accumulate_latency;
+                self.time.incr_cycles(self.timings.initiation_interval);
+            }
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick()
+                            + accumulator_free.saturating_sub(self.time.tick().time()),
+                        data: accum.unwrap(),
+                    },
+                )
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "Premature End of Sender {:?} on TreeReduce {:?}",
+                        self.output.id(),
+                        self.id
+                    )
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use super::{TreeReduce, TreeReduceTimings};
+
+    #[test]
+    fn tree_reduce_test() {
+        const ROW: usize = 30;
+        const ROWS: u64 = 8;
+        let mut elapsed = vec![];
+        for (width, accumulate_latency) in [(1, 1), (4, 1), (8, 1), (8, 4)] {
+            let mut builder = ProgramBuilder::default();
+            let inputs = (0..width)
+                .map(|lane| {
+                    let (snd, rcv) = builder.bounded(16);
+                    builder.add_child(GeneratorContext::new(
+                        move || {
+                            (0..ROWS * ROW as u64)
+                                .filter(move |i| *i as usize % ROW % width == lane)
+                        },
+                        snd,
+                    ));
+                    rcv
+                })
+                .collect();
+            let (out_snd, out_rcv) = builder.bounded(16);
+            builder.add_child(TreeReduce::new(
+                ROW,
+                inputs,
+                out_snd,
+                |a, b| a + b,
+                TreeReduceTimings {
+                    initiation_interval: 1,
+                    level_latency: 2,
+                    accumulate_latency,
+                    reset_time: 0,
+                },
+            ));
+            builder.add_child(CheckerContext::new(
+                || (0..ROWS).map(|row| (row * ROW as u64..(row + 1) * ROW as u64).sum()),
+                out_rcv,
+            ));
+            let executed = builder
+                .initialize(Default::default())
+                .unwrap()
+                .run(Default::default());
+            elapsed.push(executed.elapsed_cycles().unwrap());
+        }
+        dbg!(&elapsed);
+        // Wider trees go faster, unless the accumulator can't keep up.
+        assert!(elapsed[1] < elapsed[0]);
+        assert!(elapsed[2] < elapsed[1]);
+        assert!(elapsed[2] < elapsed[3]);
+    }
+}
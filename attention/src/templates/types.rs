@@ -33,16 +33,17 @@ where
     A: DAMType,
 {
     fn dam_size(&self) -> usize {
-        todo!()
+        self.0.iter().map(|x| x.dam_size()).sum()
     }
 }
 
+/// An empty tensor
 impl<A, D: Dimension> Default for Tensor<A, D>
 where
     A: DAMType,
 {
     fn default() -> Self {
-        todo!()
+        Self(ArcArray::default(D::default()))
     }
 }
 
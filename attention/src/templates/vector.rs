@@ -0,0 +1,395 @@
+use dam::context_tools::*;
+
+use super::BroadcastSender;
+
+/// A stream element holding several values, e.g. a row of V or the lanes of a SIMD unit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Vector<T> {
+    pub value: Vec<T>,
+}
+
+impl<T: DAMType> DAMType for Vector<T> {
+    fn dam_size(&self) -> usize {
+        self.value.iter().map(|x| x.dam_size()).sum()
+    }
+}
+
+impl<T> From<Vec<T>> for Vector<T> {
+    fn from(value: Vec<T>) -> Self {
+        Self { value }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PackTimings {
+    /// Cycles per scalar
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+/// Collects every `width` consecutive scalars into a [Vector].
+#[context_macro]
+pub struct Pack<T: DAMType> {
+    input: Receiver<T>,
+    output: BroadcastSender<Vector<T>>,
+    width: usize,
+    timings: PackTimings,
+}
+
+impl<T: DAMType> Pack<T>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<T>,
+        output: BroadcastSender<Vector<T>>,
+        width: usize,
+        timings: PackTimings,
+    ) -> Self {
+        assert!(width > 0);
+        let s = Self {
+            input,
+            output,
+            width,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType> Context for Pack<T> {
+    fn run(&mut self) {
+        loop {
+            let mut value = Vec::with_capacity(self.width);
+            for iter in 0..self.width {
+                match self.input.dequeue(&self.time) {
+                    Ok(ChannelElement { time: _, data }) => value.push(data),
+                    Err(_) if iter == 0 => return,
+                    Err(_) => panic!(
+                        "Premature End of Receiver {:?} on Pack {:?}",
+                        self.input.id(),
+                        self.id
+                    ),
+                }
+                self.time.incr_cycles(self.timings.initiation_interval);
+            }
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data: Vector { value },
+                    },
+                )
+                .unwrap_or_else(|_| panic!("Premature End of Sender on Pack {:?}", self.id));
+        }
+    }
+}
+
+/// Streams out the values of every [Vector] one at a time.
+#[context_macro]
+pub struct Unpack<T: DAMType> {
+    input: Receiver<Vector<T>>,
+    output: BroadcastSender<T>,
+    timings: PackTimings,
+}
+
+impl<T: DAMType> Unpack<T>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Receiver<Vector<T>>,
+        output: BroadcastSender<T>,
+        timings: PackTimings,
+    ) -> Self {
+        let s = Self {
+            input,
+            output,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<T: DAMType> Context for Unpack<T> {
+    fn run(&mut self) {
+        loop {
+            let vector = match self.input.dequeue(&self.time) {
+                Ok(ChannelElement { time: _, data }) => data,
+                Err(_) => return,
+            };
+            for data in vector.value {
+                self.output
+                    .enqueue(
+                        &self.time,
+                        ChannelElement {
+                            time: self.time.tick() + self.timings.latency,
+                            data,
+                        },
+                    )
+                    .unwrap_or_else(|_| panic!("Premature End of Sender on Unpack {:?}", self.id));
+                self.time.incr_cycles(self.timings.initiation_interval);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VectorTimings {
+    /// Values processed per initiation interval, so a vector takes ceil(len / lanes) intervals
+    pub lanes: usize,
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+impl VectorTimings {
+    fn cycles(&self, len: usize) -> u64 {
+        len.div_ceil(self.lanes).max(1) as u64 * self.initiation_interval
+    }
+}
+
+/// Applies a function elementwise to vectors of the same length, one from each input.
+#[context_macro]
+pub struct VectorMap<InT: DAMType, OutT: DAMType, MapF> {
+    input: Vec<Receiver<Vector<InT>>>,
+    output: BroadcastSender<Vector<OutT>>,
+    mapf: MapF,
+    timings: VectorTimings,
+}
+
+impl<InT: DAMType, OutT: DAMType, MapF> VectorMap<InT, OutT, MapF>
+where
+    Self: Context,
+{
+    pub fn new(
+        input: Vec<Receiver<Vector<InT>>>,
+        output: BroadcastSender<Vector<OutT>>,
+        mapf: MapF,
+        timings: VectorTimings,
+    ) -> Self {
+        assert!(timings.lanes > 0);
+        let s = Self {
+            input,
+            output,
+            mapf,
+            timings,
+            context_info: Default::default(),
+        };
+        s.input.iter().for_each(|chn| chn.attach_receiver(&s));
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<InT: DAMType, OutT: DAMType, MapF> Context for VectorMap<InT, OutT, MapF>
+where
+    MapF: Fn(&[InT]) -> OutT + Sync + Send,
+{
+    fn run(&mut self) {
+        loop {
+            let dequeued: Vec<_> = self
+                .input
+                .iter()
+                .map(|chn| chn.dequeue(&self.time))
+                .collect();
+            if dequeued.iter().any(|v| v.is_err()) {
+                return;
+            }
+            let vectors: Vec<_> = dequeued.into_iter().map(|v| v.unwrap().data).collect();
+            let len = vectors[0].value.len();
+            assert!(
+                vectors.iter().all(|vector| vector.value.len() == len),
+                "Vectors of different lengths on VectorMap {:?}",
+                self.id
+            );
+            let value: Vec<_> = (0..len)
+                .map(|i| {
+                    let args: Vec<_> = vectors.iter().map(|v| v.value[i].clone()).collect();
+                    (self.mapf)(&args)
+                })
+                .collect();
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data: Vector { value },
+                    },
+                )
+                .unwrap_or_else(|_| panic!("Premature End of Sender on VectorMap {:?}", self.id));
+            self.time.incr_cycles(self.timings.cycles(len));
+        }
+    }
+}
+
+/// Combines every value of a vector with the matching scalar, e.g. to scale a row by its sum.
+#[context_macro]
+pub struct VectorScalarMap<VecT: DAMType, ScalarT: DAMType, OutT: DAMType, MapF> {
+    vectors: Receiver<Vector<VecT>>,
+    scalars: Receiver<ScalarT>,
+    output: BroadcastSender<Vector<OutT>>,
+    mapf: MapF,
+    timings: VectorTimings,
+}
+
+impl<VecT: DAMType, ScalarT: DAMType, OutT: DAMType, MapF>
+    VectorScalarMap<VecT, ScalarT, OutT, MapF>
+where
+    Self: Context,
+{
+    pub fn new(
+        vectors: Receiver<Vector<VecT>>,
+        scalars: Receiver<ScalarT>,
+        output: BroadcastSender<Vector<OutT>>,
+        mapf: MapF,
+        timings: VectorTimings,
+    ) -> Self {
+        assert!(timings.lanes > 0);
+        let s = Self {
+            vectors,
+            scalars,
+            output,
+            mapf,
+            timings,
+            context_info: Default::default(),
+        };
+        s.vectors.attach_receiver(&s);
+        s.scalars.attach_receiver(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+}
+
+impl<VecT: DAMType, ScalarT: DAMType, OutT: DAMType, MapF> Context
+    for VectorScalarMap<VecT, ScalarT, OutT, MapF>
+where
+    MapF: Fn(VecT, &ScalarT) -> OutT + Sync + Send,
+{
+    fn run(&mut self) {
+        loop {
+            let (vector, scalar) = match (
+                self.vectors.dequeue(&self.time),
+                self.scalars.dequeue(&self.time),
+            ) {
+                (Ok(vector), Ok(scalar)) => (vector.data, scalar.data),
+                (Err(_), Err(_)) => return,
+                _ => panic!(
+                    "Premature End of Receiver {:?} or {:?} on VectorScalarMap {:?}",
+                    self.vectors.id(),
+                    self.scalars.id(),
+                    self.id
+                ),
+            };
+            let len = vector.value.len();
+            let value: Vec<_> = vector
+                .value
+                .into_iter()
+                .map(|x| (self.mapf)(x, &scalar))
+                .collect();
+            self.output
+                .enqueue(
+                    &self.time,
+                    ChannelElement {
+                        time: self.time.tick() + self.timings.latency,
+                        data: Vector { value },
+                    },
+                )
+                .unwrap_or_else(|_| {
+                    panic!("Premature End of Sender on VectorScalarMap {:?}", self.id)
+                });
+            self.time.incr_cycles(self.timings.cycles(len));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::BroadcastSender;
+
+    use super::{Pack, PackTimings, Unpack, VectorMap, VectorScalarMap, VectorTimings};
+
+    #[test]
+    fn vector_test() {
+        // Computes (a + b) * s, with a and b packed into vectors of 6 and one s per vector.
+        const WIDTH: usize = 6;
+        const VECTORS: u64 = 10;
+        let len = WIDTH as u64 * VECTORS;
+        let pack_timings = PackTimings {
+            initiation_interval: 1,
+            latency: 1,
+        };
+        let vector_timings = VectorTimings {
+            lanes: 4,
+            initiation_interval: 1,
+            latency: 2,
+        };
+
+        let mut builder = ProgramBuilder::default();
+        let mut packed = vec![];
+        for offset in [0, 1000] {
+            let (snd, rcv) = builder.bounded(8);
+            let (vec_snd, vec_rcv) = builder.bounded(2);
+            builder.add_child(GeneratorContext::new(move || offset..offset + len, snd));
+            builder.add_child(Pack::new(
+                rcv,
+                BroadcastSender {
+                    targets: vec![vec_snd],
+                },
+                WIDTH,
+                pack_timings,
+            ));
+            packed.push(vec_rcv);
+        }
+        let (sum_snd, sum_rcv) = builder.bounded(2);
+        builder.add_child(VectorMap::new(
+            packed,
+            BroadcastSender {
+                targets: vec![sum_snd],
+            },
+            |x: &[u64]| x[0] + x[1],
+            vector_timings,
+        ));
+
+        let (scalar_snd, scalar_rcv) = builder.bounded(2);
+        builder.add_child(GeneratorContext::new(move || 1..=VECTORS, scalar_snd));
+        let (scaled_snd, scaled_rcv) = builder.bounded(2);
+        builder.add_child(VectorScalarMap::new(
+            sum_rcv,
+            scalar_rcv,
+            BroadcastSender {
+                targets: vec![scaled_snd],
+            },
+            |x: u64, s: &u64| x * s,
+            vector_timings,
+        ));
+
+        let (out_snd, out_rcv) = builder.bounded(8);
+        builder.add_child(Unpack::new(
+            scaled_rcv,
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            pack_timings,
+        ));
+        builder.add_child(CheckerContext::new(
+            move || (0..len).map(|i| (2 * i + 1000) * (i / WIDTH as u64 + 1)),
+            out_rcv,
+        ));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+}
@@ -1,8 +1,8 @@
-use dam::context_tools::*;
+use dam::{context_tools::*, structures::TimeManager};
 
 use super::BroadcastSender;
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Pair<A, B>(pub A, pub B);
 
 impl<A: DAMType, B: DAMType> DAMType for Pair<A, B> {
@@ -70,3 +70,295 @@ impl<LeftT: DAMType, RightT: DAMType> Context for Zip<LeftT, RightT> {
         }
     }
 }
+
+/// The element-wise join of several streams.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Joined<T>(pub T);
+
+impl<T: DAMType> DAMType for Joined<Vec<T>> {
+    fn dam_size(&self) -> usize {
+        self.0.iter().map(|x| x.dam_size()).sum()
+    }
+}
+
+/// A group of streams that are consumed in lockstep, one element from each at a time.
+/// Implemented for Vec<Receiver<T>> and for tuples of receivers of up to 6 different types.
+pub trait ZipInputs: Send + Sync {
+    type Item;
+
+    fn attach_receivers(&self, ctx: &dyn Context);
+
+    /// Dequeues the next element of every stream.
+    /// If any of them has ended, returns which ones did instead.
+    fn dequeue_all(&self, time: &TimeManager) -> Result<Self::Item, Vec<bool>>;
+
+    /// Dequeues every stream until it ends.
+    fn drain_all(&self, time: &TimeManager);
+}
+
+impl<T: DAMType> ZipInputs for Vec<Receiver<T>> {
+    type Item = Vec<T>;
+
+    fn attach_receivers(&self, ctx: &dyn Context) {
+        self.iter().for_each(|chn| chn.attach_receiver(ctx));
+    }
+
+    fn dequeue_all(&self, time: &TimeManager) -> Result<Self::Item, Vec<bool>> {
+        self.iter().for_each(|chn| {
+            let _ = chn.peek_next(time);
+        });
+        let dequeued: Vec<_> = self.iter().map(|chn| chn.dequeue(time)).collect();
+        if dequeued.iter().any(|v| v.is_err()) {
+            return Err(dequeued.iter().map(|v| v.is_err()).collect());
+        }
+        Ok(dequeued.into_iter().map(|v| v.unwrap().data).collect())
+    }
+
+    fn drain_all(&self, time: &TimeManager) {
+        self.iter()
+            .for_each(|chn| while chn.dequeue(time).is_ok() {});
+    }
+}
+
+macro_rules! impl_zip_inputs {
+    ($($name:ident: $idx:tt),+) => {
+        impl<$($name: DAMType),+> DAMType for Joined<($($name,)+)> {
+            fn dam_size(&self) -> usize {
+                0 $(+ self.0.$idx.dam_size())+
+            }
+        }
+
+        impl<$($name: DAMType),+> ZipInputs for ($(Receiver<$name>,)+) {
+            type Item = ($($name,)+);
+
+            fn attach_receivers(&self, ctx: &dyn Context) {
+                $(self.$idx.attach_receiver(ctx);)+
+            }
+
+            fn dequeue_all(&self, time: &TimeManager) -> Result<Self::Item, Vec<bool>> {
+                $(let _ = self.$idx.peek_next(time);)+
+                let dequeued = ($(self.$idx.dequeue(time),)+);
+                let ended = vec![$(dequeued.$idx.is_err()),+];
+                if ended.iter().any(|&e| e) {
+                    return Err(ended);
+                }
+                Ok(($(dequeued.$idx.unwrap().data,)+))
+            }
+
+            fn drain_all(&self, time: &TimeManager) {
+                $(while self.$idx.dequeue(time).is_ok() {})+
+            }
+        }
+    };
+}
+
+impl_zip_inputs!(A: 0, B: 1);
+impl_zip_inputs!(A: 0, B: 1, C: 2);
+impl_zip_inputs!(A: 0, B: 1, C: 2, D: 3);
+impl_zip_inputs!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_zip_inputs!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+
+pub struct ZipTimings {
+    pub initiation_interval: u64,
+    pub latency: u64,
+}
+
+/// Sent by [ZipN] when some of its inputs end before the others.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ZipMismatch {
+    /// Elements joined before the first input ended
+    pub joined: usize,
+    /// Positions of the inputs that had ended
+    pub ended: Vec<usize>,
+}
+
+impl DAMType for ZipMismatch {
+    fn dam_size(&self) -> usize {
+        64 * (1 + self.ended.len())
+    }
+}
+
+/// Joins any number of streams element-wise, e.g. a tuple of receivers of different types.
+/// If some inputs end before the others, the output stops at the shortest one, the rest of the
+/// longer inputs is drained, and a [ZipMismatch] is sent on the report channel if there is one.
+#[context_macro]
+pub struct ZipN<I: ZipInputs>
+where
+    Joined<I::Item>: DAMType,
+{
+    inputs: I,
+    output: BroadcastSender<Joined<I::Item>>,
+    timings: ZipTimings,
+    report: Option<Sender<ZipMismatch>>,
+}
+
+impl<I: ZipInputs> ZipN<I>
+where
+    Joined<I::Item>: DAMType,
+{
+    pub fn new(inputs: I, output: BroadcastSender<Joined<I::Item>>, timings: ZipTimings) -> Self {
+        let s = Self {
+            inputs,
+            output,
+            timings,
+            report: None,
+            context_info: Default::default(),
+        };
+        s.inputs.attach_receivers(&s);
+        s.output.attach_sender(&s);
+        s
+    }
+
+    /// Reports inputs that end early on `report`.
+    pub fn with_mismatch_report(mut self, report: Sender<ZipMismatch>) -> Self {
+        report.attach_sender(&self);
+        self.report = Some(report);
+        self
+    }
+}
+
+impl<I: ZipInputs> Context for ZipN<I>
+where
+    Joined<I::Item>: DAMType,
+{
+    fn run(&mut self) {
+        let mut joined = 0;
+        loop {
+            match self.inputs.dequeue_all(&self.time) {
+                Ok(data) => {
+                    self.output
+                        .enqueue(
+                            &self.time,
+                            ChannelElement {
+                                time: self.time.tick() + self.timings.latency,
+                                data: Joined(data),
+                            },
+                        )
+                        .unwrap();
+                    self.time.incr_cycles(self.timings.initiation_interval);
+                    joined += 1;
+                }
+                Err(ended) if ended.iter().all(|&e| e) => return,
+                Err(ended) => {
+                    if let Some(report) = &self.report {
+                        report
+                            .enqueue(
+                                &self.time,
+                                ChannelElement {
+                                    time: self.time.tick(),
+                                    data: ZipMismatch {
+                                        joined,
+                                        ended: (0..ended.len()).filter(|&i| ended[i]).collect(),
+                                    },
+                                },
+                            )
+                            .unwrap();
+                    }
+                    self.inputs.drain_all(&self.time);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dam::{
+        simulation::ProgramBuilder,
+        utility_contexts::{CheckerContext, GeneratorContext},
+    };
+
+    use crate::templates::BroadcastSender;
+
+    use super::{Joined, ZipMismatch, ZipN, ZipTimings};
+
+    #[test]
+    fn zip_n_test() {
+        const LEN: u64 = 64;
+        let mut builder = ProgramBuilder::default();
+        let (a_snd, a_rcv) = builder.bounded(8);
+        let (b_snd, b_rcv) = builder.bounded(8);
+        let (c_snd, c_rcv) = builder.bounded(8);
+        let (out_snd, out_rcv) = builder.bounded(8);
+        builder.add_child(GeneratorContext::new(|| 0..LEN, a_snd));
+        builder.add_child(GeneratorContext::new(
+            || (0..LEN).map(|x| x as f32 * 0.5),
+            b_snd,
+        ));
+        builder.add_child(GeneratorContext::new(
+            || (0..LEN).map(|x| x as usize % 3),
+            c_snd,
+        ));
+        builder.add_child(ZipN::new(
+            (a_rcv, b_rcv, c_rcv),
+            BroadcastSender {
+                targets: vec![out_snd],
+            },
+            ZipTimings {
+                initiation_interval: 1,
+                latency: 2,
+            },
+        ));
+        builder.add_child(CheckerContext::new(
+            || (0..LEN).map(|x| Joined((x, x as f32 * 0.5, x as usize % 3))),
+            out_rcv,
+        ));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+
+    #[test]
+    fn zip_n_mismatch_test() {
+        const LEN: u64 = 64;
+        let mut builder = ProgramBuilder::default();
+        let (a_snd, a_rcv) = builder.bounded(8);
+        let (b_snd, b_rcv) = builder.bounded(8);
+        let (c_snd, c_rcv) = builder.bounded(8);
+        let (out_snd, out_rcv) = builder.bounded(8);
+        let (report_snd, report_rcv) = builder.bounded(1);
+        builder.add_child(GeneratorContext::new(|| 0..LEN, a_snd));
+        builder.add_child(GeneratorContext::new(
+            || (0..LEN).map(|x| x as f32 * 0.5),
+            b_snd,
+        ));
+        // One element short
+        builder.add_child(GeneratorContext::new(
+            || (0..LEN - 1).map(|x| x as usize % 3),
+            c_snd,
+        ));
+        builder.add_child(
+            ZipN::new(
+                (a_rcv, b_rcv, c_rcv),
+                BroadcastSender {
+                    targets: vec![out_snd],
+                },
+                ZipTimings {
+                    initiation_interval: 1,
+                    latency: 2,
+                },
+            )
+            .with_mismatch_report(report_snd),
+        );
+        builder.add_child(CheckerContext::new(
+            || (0..LEN - 1).map(|x| Joined((x, x as f32 * 0.5, x as usize % 3))),
+            out_rcv,
+        ));
+        // The zipped output stops at the shortest input, which is reported.
+        builder.add_child(CheckerContext::new(
+            || {
+                std::iter::once(ZipMismatch {
+                    joined: LEN as usize - 1,
+                    ended: vec![2],
+                })
+            },
+            report_rcv,
+        ));
+        builder
+            .initialize(Default::default())
+            .unwrap()
+            .run(Default::default());
+    }
+}